@@ -0,0 +1,79 @@
+//! Allocations per second of converting frames into fresh strings, as the parser used to, against
+//! refilling one reused buffer. Run with `cargo bench`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use terminal_player::{ascii, Config};
+
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const WIDTH: usize = 1920;
+const HEIGHT: usize = 1080;
+const FRAMES: usize = 200;
+
+/// Converts `FRAMES` frames with `convert`, returning allocations and time taken.
+fn measure(frame: &[u8], mut convert: impl FnMut(&[u8])) -> (usize, Duration) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let started = Instant::now();
+    for _ in 0..FRAMES {
+        convert(frame);
+    }
+    let elapsed = started.elapsed();
+    (ALLOCATIONS.load(Ordering::Relaxed) - allocations, elapsed)
+}
+
+fn report(name: &str, (allocations, elapsed): (usize, Duration)) {
+    println!(
+        "{:<10} {:>8.1} frames/s {:>10.1} allocations/s ({} allocations over {} frames)",
+        name,
+        FRAMES as f64 / elapsed.as_secs_f64(),
+        allocations as f64 / elapsed.as_secs_f64(),
+        allocations,
+        FRAMES
+    );
+}
+
+fn main() {
+    let args: Vec<String> = ["terminal_player", "-w", "240", "bench.mp4"]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    let mut cfg = Config::from_args(&args).unwrap();
+    cfg.configure_for(WIDTH, HEIGHT, 60.0);
+    let frame: Vec<u8> = (0..WIDTH * HEIGHT * 3).map(|i| (i % 251) as u8).collect();
+
+    report(
+        "fresh",
+        measure(&frame, |frame| {
+            std::hint::black_box(ascii::rgb_to_ascii(frame, &cfg));
+        }),
+    );
+
+    let mut buffer = String::with_capacity(cfg.frame_size);
+    report(
+        "reused",
+        measure(&frame, |frame| {
+            buffer.clear();
+            ascii::rgb_to_ascii_buff(frame, &cfg, &mut buffer);
+            std::hint::black_box(&buffer);
+        }),
+    );
+}
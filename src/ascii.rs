@@ -0,0 +1,1131 @@
+//! RGB to ASCII conversion. Nothing in here touches video_rs or does IO, so it also builds for
+//! `wasm32-unknown-unknown`.
+use std::fmt::Write as _;
+
+use crate::{globals, Config};
+
+const CHAR_MAP: &str = " ,\":;Il!i~+_-?][}{1)(|\\/tfjrxnuvczXYUJCLQ0OZmwqpdbkhao*#MW&8%B@$";
+/// Block shades ordered from dark to bright, the fill half of a `rich` cell.
+const SHADES: [char; 5] = [' ', '░', '▒', '▓', '█'];
+/// Empty braille pattern, dots are added by setting bits.
+const BRAILLE_BASE: u32 = 0x2800;
+/// Bit of each dot in a braille cell, indexed by row then column. The first six dots run down
+/// the columns, the bottom row was added later on.
+const BRAILLE_DOTS: [[u8; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+/// Upper half block, its foreground color paints the top pixel and its background the bottom.
+const HALF_BLOCK: char = '▀';
+/// Edge glyphs by the direction of the gradient across them, in 45 degree steps turning from
+/// right towards down.
+const EDGE_GLYPHS: [char; 4] = ['|', '/', '-', '\\'];
+/// Gradient strength, in luminance steps per cell, from which a cell counts as an edge.
+const EDGE_THRESHOLD: f32 = 32.0;
+/// Luminance above which a braille dot is raised, unless a threshold is given.
+const BRAILLE_THRESHOLD: f32 = 128.0;
+/// Luminance standard deviation below which a block counts as flat in `rich` mode.
+const RICH_FLAT_STD_DEV: f32 = 24.0;
+pub const RESET_COLOR: &str = "\x1B[0m";
+/// Longest truecolor escape, `\x1B[38;2;255;255;255m`.
+const MAX_COLOR_ESCAPE_LEN: usize = 19;
+/// Channel levels of the xterm 256-color cube.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+/// Clear screen and move cursor to top-left corner.
+pub const CLEAR_SCREEN: &str = "\x1B[2J\x1B[1;1H";
+/// Move cursor to top-left corner, leaving the screen as is.
+pub const CURSOR_HOME: &str = "\x1B[H";
+/// Clear from the cursor to the end of the line.
+pub const CLEAR_LINE: &str = "\x1B[K";
+/// Clear from the cursor to the end of the screen.
+pub const CLEAR_BELOW: &str = "\x1B[J";
+pub const HIDE_CURSOR: &str = "\x1B[?25l";
+pub const SHOW_CURSOR: &str = "\x1B[?25h";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorMode {
+    #[default]
+    Mono,
+    /// 24-bit foreground escape in front of every glyph.
+    TrueColor,
+    /// Escape for the nearest color of the xterm 256-color palette, its 6x6x6 cube or grayscale
+    /// ramp, for terminals without 24-bit color.
+    Ansi256,
+}
+
+/// Which pixel of a cell's block gets sampled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SampleOffset {
+    #[default]
+    TopLeft,
+    /// The middle pixel, which catches thin features a top-left sample keeps missing.
+    Center,
+}
+
+impl std::str::FromStr for SampleOffset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "topleft" => Ok(SampleOffset::TopLeft),
+            "center" => Ok(SampleOffset::Center),
+            _ => Err(format!("Unknown sample offset {}", s)),
+        }
+    }
+}
+
+/// Channel weights turning a pixel's color into its brightness.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LumaWeights {
+    /// SD video, more weight on red and blue.
+    Rec601,
+    /// HD video and sRGB.
+    #[default]
+    Rec709,
+}
+
+impl LumaWeights {
+    fn weights(self) -> [f32; 3] {
+        match self {
+            LumaWeights::Rec601 => [0.299, 0.587, 0.114],
+            LumaWeights::Rec709 => [0.2126, 0.7152, 0.0722],
+        }
+    }
+}
+
+impl std::str::FromStr for LumaWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "601" | "rec601" => Ok(LumaWeights::Rec601),
+            "709" | "rec709" => Ok(LumaWeights::Rec709),
+            _ => Err(format!("Unknown luminance weights {}", s)),
+        }
+    }
+}
+
+/// Fixed color sets to quantize color output to, for a retro look.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Palette {
+    /// The 16 colors of IBM's CGA adapter.
+    Cga,
+    /// The original Game Boy's 4 shades of green.
+    Gameboy,
+    /// The Commodore 64's 16 colors.
+    C64,
+}
+
+impl Palette {
+    pub fn colors(&self) -> &'static [(u8, u8, u8)] {
+        match self {
+            Palette::Cga => &[
+                (0x00, 0x00, 0x00),
+                (0x00, 0x00, 0xAA),
+                (0x00, 0xAA, 0x00),
+                (0x00, 0xAA, 0xAA),
+                (0xAA, 0x00, 0x00),
+                (0xAA, 0x00, 0xAA),
+                (0xAA, 0x55, 0x00),
+                (0xAA, 0xAA, 0xAA),
+                (0x55, 0x55, 0x55),
+                (0x55, 0x55, 0xFF),
+                (0x55, 0xFF, 0x55),
+                (0x55, 0xFF, 0xFF),
+                (0xFF, 0x55, 0x55),
+                (0xFF, 0x55, 0xFF),
+                (0xFF, 0xFF, 0x55),
+                (0xFF, 0xFF, 0xFF),
+            ],
+            Palette::Gameboy => &[(15, 56, 15), (48, 98, 48), (139, 172, 15), (155, 188, 15)],
+            Palette::C64 => &[
+                (0x00, 0x00, 0x00),
+                (0xFF, 0xFF, 0xFF),
+                (0x68, 0x37, 0x2B),
+                (0x70, 0xA4, 0xB2),
+                (0x6F, 0x3D, 0x86),
+                (0x58, 0x8D, 0x43),
+                (0x35, 0x28, 0x79),
+                (0xB8, 0xC7, 0x6F),
+                (0x6F, 0x4F, 0x25),
+                (0x43, 0x39, 0x00),
+                (0x9A, 0x67, 0x59),
+                (0x44, 0x44, 0x44),
+                (0x6C, 0x6C, 0x6C),
+                (0x9A, 0xD2, 0x84),
+                (0x6C, 0x5E, 0xB5),
+                (0x95, 0x95, 0x95),
+            ],
+        }
+    }
+}
+
+impl std::str::FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cga" => Ok(Palette::Cga),
+            "gameboy" => Ok(Palette::Gameboy),
+            "c64" => Ok(Palette::C64),
+            _ => Err(format!("Unknown palette {}", s)),
+        }
+    }
+}
+
+/// Nearest color of `palette`, by euclidean distance in RGB.
+pub fn rgb_to_palette(rgb: (u8, u8, u8), palette: &[(u8, u8, u8)]) -> (u8, u8, u8) {
+    let distance = |color: &&(u8, u8, u8)| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(rgb.0, color.0) + d(rgb.1, color.1) + d(rgb.2, color.2)
+    };
+    palette.iter().min_by_key(distance).copied().unwrap_or(rgb)
+}
+
+/// Nearest color of the xterm 256-color palette, by euclidean distance in RGB. Only the 6x6x6
+/// cube (16-231) and the grayscale ramp (232-255) are considered, the 16 system colors differ
+/// from terminal to terminal.
+pub fn rgb_to_ansi256(rgb: (u8, u8, u8)) -> u8 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+    let distance = |color: (u8, u8, u8)| d(rgb.0, color.0) + d(rgb.1, color.1) + d(rgb.2, color.2);
+    // The cube's levels aren't evenly spaced, so each channel is snapped to the nearest one, which
+    // also makes for the nearest cube color overall.
+    let level = |channel: u8| {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|&i| d(channel, CUBE_LEVELS[i]))
+            .unwrap_or(0)
+    };
+    let (r, g, b) = (level(rgb.0), level(rgb.1), level(rgb.2));
+    let cube = (CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]);
+    // Ramp steps are 10 apart from 8 up to 238.
+    let mean = (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+    let step = (mean.saturating_sub(3) / 10).min(23) as u8;
+    let gray = 8 + 10 * step;
+    match distance((gray, gray, gray)) < distance(cube) {
+        true => 232 + step,
+        false => (16 + 36 * r + 6 * g + b) as u8,
+    }
+}
+
+/// Everything the conversion needs to know, independent of where the frames come from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsciiOptions {
+    /// Output width in characters.
+    pub width: usize,
+    /// Glyph ramp ordered from dark to bright.
+    pub charset: Vec<char>,
+    pub color: ColorMode,
+    /// Map bright pixels to the dark end of the ramp, for dark-on-light terminals.
+    pub invert: bool,
+    /// Applied to luminance before picking a glyph. Values above 1.0 lift dark regions.
+    pub gamma: f32,
+    pub luma: LumaWeights,
+    /// Binarize around this luminance: only cells at or below it get a glyph, denser the darker
+    /// they are, the rest stay blank. `invert` swaps the sides so only brighter cells are drawn.
+    pub threshold: Option<u8>,
+    /// Two characters per cell, a glyph for the cell's mean luminance followed by either a shade
+    /// of the same tone, when the block is flat, or the glyph again when it's textured (a solid
+    /// shade would smear the detail). Averages every pixel of the block instead of sampling one.
+    pub rich: bool,
+    /// One braille character per cell, its 2x4 dots raised where the pixel under them is
+    /// brighter than `threshold` (128 by default), for 8 times the detail of a glyph ramp.
+    pub braille: bool,
+    /// Two pixels per cell, one above the other, drawn as an upper half block colored by the top
+    /// pixel over a background colored by the bottom one. Always in color.
+    pub halfblock: bool,
+    /// Line art: cells on a strong luminance gradient get the line glyph running along the edge,
+    /// the rest stay blank.
+    pub edges: bool,
+    /// Quantize colors to a fixed palette before they're emitted.
+    pub palette: Option<Palette>,
+    /// Pixel sampled from each cell. `rich` averages the whole block and ignores this.
+    pub sample_offset: SampleOffset,
+    /// Height of a terminal character cell over its width. Cells are about twice as tall as
+    /// they're wide, so that many more source rows go into each output row.
+    pub cell_aspect: f32,
+}
+
+impl Default for AsciiOptions {
+    fn default() -> Self {
+        AsciiOptions {
+            width: globals::DEF_WIDTH,
+            charset: CHAR_MAP.chars().collect(),
+            color: ColorMode::Mono,
+            invert: false,
+            gamma: 1.0,
+            luma: LumaWeights::default(),
+            threshold: None,
+            rich: false,
+            braille: false,
+            halfblock: false,
+            edges: false,
+            palette: None,
+            sample_offset: SampleOffset::TopLeft,
+            cell_aspect: globals::DEF_CELL_ASPECT,
+        }
+    }
+}
+
+impl AsciiOptions {
+    /// Height over width of the area each sample stands for: a cell, or its top or bottom half
+    /// with `halfblock`.
+    pub fn sample_aspect(&self) -> f32 {
+        match self.halfblock {
+            true => self.cell_aspect / 2.0,
+            false => self.cell_aspect,
+        }
+    }
+}
+
+/// Source pixels covered by one output cell, as (columns, rows).
+pub fn sampling_rate(src_w: usize, width: usize, cell_aspect: f32) -> (usize, usize) {
+    // Rounded up, so the output is never wider than asked for.
+    let sample_x = src_w.div_ceil(width.max(1)).max(1);
+    let sample_y = (sample_x as f32 * cell_aspect).round().max(1.0) as usize;
+    (sample_x, sample_y)
+}
+
+/// Output rows for `src_h` source rows sampled every `sample_y`, two samples a row with
+/// `halfblock`.
+pub fn output_rows(src_h: usize, sample_y: usize, opts: &AsciiOptions) -> usize {
+    let samples = src_h.div_ceil(sample_y.max(1));
+    match opts.halfblock {
+        true => samples.div_ceil(2),
+        false => samples,
+    }
+}
+
+/// Bytes a frame of `cols`x`rows` cells can take at most, so its buffer never has to grow.
+/// Glyphs are counted at the byte length of the widest one in use, block shades and braille
+/// take 3 bytes each.
+pub fn frame_capacity(cols: usize, rows: usize, opts: &AsciiOptions) -> usize {
+    let mut cell = max_glyph_len(opts);
+    if opts.rich {
+        cell += cell.max(SHADES.iter().map(|c| c.len_utf8()).max().unwrap_or(1));
+    }
+    let mut line_end = 1;
+    if opts.color != ColorMode::Mono {
+        cell += MAX_COLOR_ESCAPE_LEN;
+        line_end += RESET_COLOR.len();
+    }
+    if opts.halfblock {
+        // The background escape is as long as the foreground one.
+        cell = HALF_BLOCK.len_utf8() + 2 * MAX_COLOR_ESCAPE_LEN;
+    }
+    (cols * cell + line_end) * rows
+}
+
+/// Byte length of the widest glyph the ramp can produce.
+fn max_glyph_len(opts: &AsciiOptions) -> usize {
+    if opts.braille {
+        return 3;
+    }
+    opts.charset.iter().map(|c| c.len_utf8()).max().unwrap_or(1)
+}
+
+/// Converts a tightly packed RGB24 image of `src_w`x`src_h` pixels.
+pub fn convert_rgb(rgb: &[u8], src_w: usize, src_h: usize, opts: &AsciiOptions) -> String {
+    let sampling = sampling_rate(src_w, opts.width, opts.sample_aspect());
+    let (cols, rows) = (
+        src_w.div_ceil(sampling.0),
+        output_rows(src_h, sampling.1, opts),
+    );
+    let mut frame_str = String::with_capacity(frame_capacity(cols, rows, opts));
+    render(rgb, src_w * 3, sampling, opts, &mut frame_str);
+    frame_str
+}
+
+pub fn rgb_to_ascii(rgb: &[u8], cfg: &Config) -> String {
+    let mut frame_str = String::with_capacity(cfg.frame_size);
+    rgb_to_ascii_buff(rgb, cfg, &mut frame_str);
+    frame_str
+}
+
+pub fn rgb_to_ascii_buff(rgb: &[u8], cfg: &Config, buff: &mut String) {
+    render(
+        rgb,
+        cfg.video_size.0 * 3,
+        cfg.sampling_rate,
+        &cfg.ascii,
+        buff,
+    );
+}
+
+/// Luminance of every sampled cell, the intermediate form for effects that work on whole frames
+/// rather than single pixels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LumaGrid {
+    pub cols: usize,
+    pub cells: Vec<f32>,
+}
+
+impl LumaGrid {
+    pub fn from_rgb(
+        rgb: &[u8],
+        row_len: usize,
+        sampling: (usize, usize),
+        offset: SampleOffset,
+        weights: LumaWeights,
+    ) -> LumaGrid {
+        let mut cols = 0;
+        let mut cells = Vec::new();
+        for row in sampled_rows(rgb, row_len, sampling, offset) {
+            let start = cells.len();
+            cells.extend(row.map(|pixel| luminance(pixel, weights)));
+            cols = cells.len() - start;
+        }
+        LumaGrid { cols, cells }
+    }
+
+    pub fn from_frame(rgb: &[u8], cfg: &Config) -> LumaGrid {
+        LumaGrid::from_rgb(
+            rgb,
+            cfg.video_size.0 * 3,
+            cfg.sampling_rate,
+            cfg.ascii.sample_offset,
+            cfg.ascii.luma,
+        )
+    }
+
+    /// Blends towards `other`, `t` of 0.0 being `self` and 1.0 being `other`.
+    pub fn lerp(&self, other: &LumaGrid, t: f32) -> LumaGrid {
+        let cells = self
+            .cells
+            .iter()
+            .zip(&other.cells)
+            .map(|(a, b)| a + (b - a) * t)
+            .collect();
+        LumaGrid {
+            cols: self.cols,
+            cells,
+        }
+    }
+
+    /// Renders the grid in mono, colors are gone by the time luminance is sampled.
+    pub fn to_ascii(&self, opts: &AsciiOptions) -> String {
+        let rows = self.cells.len().div_ceil(self.cols.max(1));
+        let mut frame_str = String::with_capacity(self.cells.len() * max_glyph_len(opts) + rows);
+        self.push_ascii(opts, &mut frame_str);
+        frame_str
+    }
+
+    /// Like `to_ascii`, appending to `buff` so its allocation can be reused.
+    pub fn push_ascii(&self, opts: &AsciiOptions, buff: &mut String) {
+        if opts.edges {
+            return self.push_edges(buff, opts.cell_aspect);
+        }
+        for row in self.cells.chunks(self.cols.max(1)) {
+            buff.extend(row.iter().map(|lum| glyph_for(*lum, opts)));
+            buff.push('\n');
+        }
+    }
+
+    /// Appends the grid as line art, by the Sobel gradient over each cell and its neighbors.
+    /// Cells past the border are taken to be like the nearest one inside, so borders only show
+    /// edges running through the image. Rows are `cell_aspect` times as tall as columns are
+    /// wide, which the vertical gradient is scaled back by for its direction.
+    fn push_edges(&self, buff: &mut String, cell_aspect: f32) {
+        let cols = self.cols.max(1);
+        let rows = self.cells.len() / cols;
+        let at = |x: isize, y: isize| {
+            let x = x.clamp(0, cols as isize - 1) as usize;
+            let y = y.clamp(0, rows as isize - 1) as usize;
+            self.cells[y * cols + x]
+        };
+        for y in 0..rows as isize {
+            for x in 0..cols as isize {
+                let column = |dx| at(x + dx, y - 1) + 2.0 * at(x + dx, y) + at(x + dx, y + 1);
+                let row = |dy| at(x - 1, y + dy) + 2.0 * at(x, y + dy) + at(x + 1, y + dy);
+                // Sobel kernels weigh up to 4 cells a side.
+                let gx = (column(1) - column(-1)) / 4.0;
+                let gy = (row(1) - row(-1)) / 4.0;
+                buff.push(edge_glyph(gx, gy / cell_aspect, gx.hypot(gy)));
+            }
+            buff.push('\n');
+        }
+    }
+}
+
+/// Glyph for a gradient of (`gx`, `gy`), y pointing down, with `strength` as its magnitude.
+/// Edges run across the gradient.
+fn edge_glyph(gx: f32, gy: f32, strength: f32) -> char {
+    if strength < EDGE_THRESHOLD {
+        return ' ';
+    }
+    // Gradient direction folded into half a turn, 0 pointing right across a vertical edge and a
+    // quarter turn pointing down across a horizontal one.
+    let angle = gy.atan2(gx).rem_euclid(std::f32::consts::PI);
+    let step = (angle / std::f32::consts::FRAC_PI_4).round() as usize;
+    EDGE_GLYPHS[step % 4]
+}
+
+/// The sampled pixel of every cell, row by row. Blocks cut short by the image's edge are
+/// centered on what's left of them.
+fn sampled_rows(
+    rgb: &[u8],
+    row_len: usize,
+    sampling: (usize, usize),
+    offset: SampleOffset,
+) -> impl Iterator<Item = impl Iterator<Item = &[u8]>> {
+    let pick = move |len: usize| match offset {
+        SampleOffset::TopLeft => 0,
+        SampleOffset::Center => len / 2,
+    };
+    rgb.chunks(row_len * sampling.1).map(move |block| {
+        let start = pick(block.len().div_ceil(row_len)) * row_len;
+        let row = &block[start..(start + row_len).min(block.len())];
+        row.chunks(sampling.0 * 3).map(move |cell| {
+            let x = pick(cell.len() / 3) * 3;
+            &cell[x..x + 3]
+        })
+    })
+}
+
+fn render(
+    rgb: &[u8],
+    row_len: usize,
+    sampling: (usize, usize),
+    opts: &AsciiOptions,
+    buff: &mut String,
+) {
+    if opts.rich {
+        return render_rich(rgb, row_len, sampling, opts, buff);
+    }
+    if opts.braille {
+        return render_braille(rgb, row_len, sampling, opts, buff);
+    }
+    if opts.halfblock {
+        return render_halfblock(rgb, row_len, sampling, opts, buff);
+    }
+    if opts.edges {
+        let grid = LumaGrid::from_rgb(rgb, row_len, sampling, opts.sample_offset, opts.luma);
+        return grid.push_edges(buff, opts.cell_aspect);
+    }
+    for row in sampled_rows(rgb, row_len, sampling, opts.sample_offset) {
+        for pixel in row {
+            if opts.color != ColorMode::Mono {
+                push_color(buff, (pixel[0], pixel[1], pixel[2]), opts);
+            }
+            buff.push(rgb_to_ascii_char(pixel, opts));
+        }
+        if opts.color != ColorMode::Mono {
+            buff.push_str(RESET_COLOR);
+        }
+        buff.push('\n');
+    }
+}
+
+fn render_rich(
+    rgb: &[u8],
+    row_len: usize,
+    sampling: (usize, usize),
+    opts: &AsciiOptions,
+    buff: &mut String,
+) {
+    let rows: Vec<&[u8]> = rgb.chunks(row_len).collect();
+    let width = row_len / 3;
+    for block in rows.chunks(sampling.1) {
+        for x in (0..width).step_by(sampling.0) {
+            let x_end = (x + sampling.0).min(width);
+            let (mut sum, mut sum_sq, mut rgb_sum) = (0.0, 0.0, [0u32; 3]);
+            let mut count = 0;
+            for pixel in block.iter().flat_map(|row| row[x * 3..x_end * 3].chunks(3)) {
+                let lum = luminance(pixel, opts.luma);
+                sum += lum;
+                sum_sq += lum * lum;
+                for (total, channel) in rgb_sum.iter_mut().zip(pixel) {
+                    *total += *channel as u32;
+                }
+                count += 1;
+            }
+            let mean = sum / count as f32;
+            let std_dev = (sum_sq / count as f32 - mean * mean).max(0.0).sqrt();
+
+            if opts.color != ColorMode::Mono {
+                let [r, g, b] = rgb_sum.map(|total| (total / count) as u8);
+                push_color(buff, (r, g, b), opts);
+            }
+            let glyph = glyph_for(mean, opts);
+            buff.push(glyph);
+            buff.push(if std_dev < RICH_FLAT_STD_DEV {
+                shade_for(mean, opts)
+            } else {
+                glyph
+            });
+        }
+        if opts.color != ColorMode::Mono {
+            buff.push_str(RESET_COLOR);
+        }
+        buff.push('\n');
+    }
+}
+
+/// Samples a 2x4 grid of dots out of every cell, each in the middle of its share of the block.
+fn render_braille(
+    rgb: &[u8],
+    row_len: usize,
+    sampling: (usize, usize),
+    opts: &AsciiOptions,
+    buff: &mut String,
+) {
+    let rows: Vec<&[u8]> = rgb.chunks(row_len).collect();
+    let width = row_len / 3;
+    let threshold = opts.threshold.map_or(BRAILLE_THRESHOLD, |t| t as f32);
+    for y in (0..rows.len()).step_by(sampling.1) {
+        for x in (0..width).step_by(sampling.0) {
+            let mut bits = 0;
+            let mut rgb_sum = [0u32; 3];
+            for (dy, row_bits) in BRAILLE_DOTS.iter().enumerate() {
+                let py = (y + (2 * dy + 1) * sampling.1 / 8).min(rows.len() - 1);
+                for (dx, bit) in row_bits.iter().enumerate() {
+                    let px = (x + (2 * dx + 1) * sampling.0 / 4).min(width - 1);
+                    let pixel = &rows[py][px * 3..px * 3 + 3];
+                    let lum = apply_gamma(luminance(pixel, opts.luma), opts.gamma);
+                    if (lum > threshold) != opts.invert {
+                        bits |= bit;
+                    }
+                    for (total, channel) in rgb_sum.iter_mut().zip(pixel) {
+                        *total += *channel as u32;
+                    }
+                }
+            }
+            if opts.color != ColorMode::Mono {
+                let [r, g, b] = rgb_sum.map(|total| (total / 8) as u8);
+                push_color(buff, (r, g, b), opts);
+            }
+            buff.push(char::from_u32(BRAILLE_BASE + bits as u32).unwrap_or(' '));
+        }
+        if opts.color != ColorMode::Mono {
+            buff.push_str(RESET_COLOR);
+        }
+        buff.push('\n');
+    }
+}
+
+/// Pairs up the sampled rows, the top one of each pair in the foreground. A last row without a
+/// partner is left on the terminal's own background.
+fn render_halfblock(
+    rgb: &[u8],
+    row_len: usize,
+    sampling: (usize, usize),
+    opts: &AsciiOptions,
+    buff: &mut String,
+) {
+    let mut rows = sampled_rows(rgb, row_len, sampling, opts.sample_offset);
+    while let Some(top) = rows.next() {
+        let mut bottom = rows.next();
+        for pixel in top {
+            push_color(buff, (pixel[0], pixel[1], pixel[2]), opts);
+            if let Some(pixel) = bottom.as_mut().and_then(|bottom| bottom.next()) {
+                push_background(buff, (pixel[0], pixel[1], pixel[2]), opts);
+            }
+            buff.push(HALF_BLOCK);
+        }
+        // Resets the background as well.
+        buff.push_str(RESET_COLOR);
+        buff.push('\n');
+    }
+}
+
+fn push_color(buff: &mut String, rgb: (u8, u8, u8), opts: &AsciiOptions) {
+    push_sgr_color(buff, 38, rgb, opts);
+}
+
+fn push_background(buff: &mut String, rgb: (u8, u8, u8), opts: &AsciiOptions) {
+    push_sgr_color(buff, 48, rgb, opts);
+}
+
+/// `layer` is 38 for the foreground, 48 for the background.
+fn push_sgr_color(buff: &mut String, layer: u8, rgb: (u8, u8, u8), opts: &AsciiOptions) {
+    let rgb = match opts.palette {
+        Some(palette) => rgb_to_palette(rgb, palette.colors()),
+        None => rgb,
+    };
+    let _ = match opts.color {
+        ColorMode::Ansi256 => write!(buff, "\x1B[{};5;{}m", layer, rgb_to_ansi256(rgb)),
+        _ => write!(buff, "\x1B[{};2;{};{};{}m", layer, rgb.0, rgb.1, rgb.2),
+    };
+}
+
+fn rgb_to_ascii_char(pixel: &[u8], opts: &AsciiOptions) -> char {
+    glyph_for(luminance(pixel, opts.luma), opts)
+}
+
+fn apply_gamma(lum: f32, gamma: f32) -> f32 {
+    if gamma == 1.0 {
+        return lum;
+    }
+    255.0 * (lum / 255.0).powf(1.0 / gamma)
+}
+
+fn shade_for(lum: f32, opts: &AsciiOptions) -> char {
+    let mut lum = apply_gamma(lum, opts.gamma);
+    if opts.invert {
+        lum = 255.0 - lum;
+    }
+    SHADES[((lum / 256.0 * SHADES.len() as f32) as usize).min(SHADES.len() - 1)]
+}
+
+fn glyph_for(lum: f32, opts: &AsciiOptions) -> char {
+    let mut lum = apply_gamma(lum, opts.gamma);
+    if let Some(threshold) = opts.threshold {
+        let threshold = threshold as f32;
+        return match opts.invert {
+            false if lum <= threshold => char_for_luminance(255.0 - lum, &opts.charset),
+            true if lum > threshold => char_for_luminance(lum, &opts.charset),
+            _ => ' ',
+        };
+    }
+    if opts.invert {
+        lum = 255.0 - lum;
+    }
+    char_for_luminance(lum, &opts.charset)
+}
+
+/// Perceived brightness of an RGB pixel, in the 0..=255 range.
+pub fn luminance(pixel: &[u8], weights: LumaWeights) -> f32 {
+    let [r, g, b] = weights.weights();
+    r * pixel[0] as f32 + g * pixel[1] as f32 + b * pixel[2] as f32
+}
+
+/// Glyph of `ramp`, ordered from dark to bright, for `lum` in 0..=255. The range is split
+/// evenly over the whole ramp, indexed by character rather than by byte so multi-byte ramps map
+/// the same way as plain ASCII ones.
+pub fn char_for_luminance(lum: f32, ramp: &[char]) -> char {
+    let index = (lum.max(0.0) / 256.0 * ramp.len() as f32) as usize;
+    ramp.get(index.min(ramp.len().saturating_sub(1)))
+        .copied()
+        .unwrap_or(' ')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn luminance_weights_channels() {
+        let cases: [([u8; 3], LumaWeights, f32); 6] = [
+            ([0, 0, 0], LumaWeights::Rec709, 0.0),
+            ([255, 255, 255], LumaWeights::Rec709, 255.0),
+            ([255, 255, 255], LumaWeights::Rec601, 255.0),
+            ([255, 0, 0], LumaWeights::Rec709, 54.21),
+            ([0, 255, 0], LumaWeights::Rec601, 149.69),
+            ([0, 0, 255], LumaWeights::Rec709, 18.41),
+        ];
+        for (pixel, weights, expected) in cases {
+            let lum = luminance(&pixel, weights);
+            assert!((lum - expected).abs() < 0.01, "{:?} {:?}", pixel, weights);
+        }
+    }
+
+    fn char_map() -> Vec<char> {
+        CHAR_MAP.chars().collect()
+    }
+
+    #[test]
+    fn char_for_luminance_table() {
+        let cases = [
+            (0.0, ' '),
+            (12.0, ':'),
+            (50.0, '-'),
+            (128.0, 'c'),
+            (255.0, '$'),
+        ];
+        for (lum, expected) in cases {
+            assert_eq!(
+                char_for_luminance(lum, &char_map()),
+                expected,
+                "lum {}",
+                lum
+            );
+        }
+    }
+
+    #[test]
+    fn char_for_luminance_spans_the_whole_ramp() {
+        let used: std::collections::HashSet<char> = (0..=255u8)
+            .map(|lum| char_for_luminance(lum as f32, &char_map()))
+            .collect();
+        assert_eq!(used.len(), CHAR_MAP.chars().count());
+        let ramp: Vec<char> = "ab".chars().collect();
+        assert_eq!(char_for_luminance(127.0, &ramp), 'a');
+        assert_eq!(char_for_luminance(128.0, &ramp), 'b');
+    }
+
+    #[test]
+    fn gamma_lifts_dark_regions() {
+        let opts = AsciiOptions {
+            gamma: 2.2,
+            ..Default::default()
+        };
+        let index_of = |c: char| CHAR_MAP.chars().position(|m| m == c).unwrap();
+        let dark = [30, 30, 30];
+        let lifted = index_of(rgb_to_ascii_char(&dark, &opts));
+        let plain = index_of(rgb_to_ascii_char(&dark, &AsciiOptions::default()));
+        assert!(lifted > plain + 10, "{} vs {}", lifted, plain);
+        assert_eq!(rgb_to_ascii_char(&[255, 255, 255], &opts), '$');
+    }
+
+    #[test]
+    fn char_for_luminance_is_monotonic() {
+        let index_of = |c: char| CHAR_MAP.chars().position(|m| m == c).unwrap();
+        let mut prev = 0;
+        for lum in 0..=255u8 {
+            let index = index_of(char_for_luminance(lum as f32, &char_map()));
+            assert!(index >= prev, "lum {} went back to index {}", lum, index);
+            assert!(index - prev <= 1, "lum {} skipped past index {}", lum, prev);
+            prev = index;
+        }
+    }
+
+    #[test]
+    fn char_for_luminance_indexes_multi_byte_ramps_by_char() {
+        let ascii: Vec<char> = "0123456789".chars().collect();
+        let braille: Vec<char> = "⠀⠁⠃⠇⠏⠟⠿⡿⣷⣿".chars().collect();
+        for lum in 0..=255u8 {
+            let index = |ramp: &[char]| {
+                let glyph = char_for_luminance(lum as f32, ramp);
+                ramp.iter().position(|c| *c == glyph)
+            };
+            assert_eq!(index(&braille), index(&ascii), "lum {}", lum);
+        }
+    }
+
+    /// 4x2 image, left half white, right half black.
+    fn split_image() -> Vec<u8> {
+        let row = [[255u8; 6], [0u8; 6]].concat();
+        [row.clone(), row].concat()
+    }
+
+    #[test]
+    fn convert_rgb_samples_cells() {
+        let opts = AsciiOptions {
+            width: 2,
+            ..Default::default()
+        };
+        assert_eq!(convert_rgb(&split_image(), 4, 2, &opts), "$ \n");
+    }
+
+    #[test]
+    fn convert_rgb_invert_flips_the_ramp() {
+        let opts = AsciiOptions {
+            width: 2,
+            invert: true,
+            ..Default::default()
+        };
+        assert_eq!(convert_rgb(&split_image(), 4, 2, &opts), " $\n");
+    }
+
+    #[test]
+    fn convert_rgb_true_color_wraps_each_glyph() {
+        let opts = AsciiOptions {
+            width: 2,
+            color: ColorMode::TrueColor,
+            ..Default::default()
+        };
+        assert_eq!(
+            convert_rgb(&split_image(), 4, 2, &opts),
+            "\x1B[38;2;255;255;255m$\x1B[38;2;0;0;0m \x1B[0m\n"
+        );
+    }
+
+    #[test]
+    fn threshold_only_draws_dark_cells() {
+        let opts = AsciiOptions {
+            threshold: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(rgb_to_ascii_char(&[0, 0, 0], &opts), '$');
+        assert_eq!(rgb_to_ascii_char(&[100, 100, 100], &opts), 'C');
+        assert_eq!(rgb_to_ascii_char(&[101, 101, 101], &opts), ' ');
+        assert_eq!(rgb_to_ascii_char(&[255, 255, 255], &opts), ' ');
+    }
+
+    #[test]
+    fn threshold_with_invert_only_draws_bright_cells() {
+        let opts = AsciiOptions {
+            threshold: Some(100),
+            invert: true,
+            ..Default::default()
+        };
+        assert_eq!(rgb_to_ascii_char(&[0, 0, 0], &opts), ' ');
+        assert_eq!(rgb_to_ascii_char(&[100, 100, 100], &opts), ' ');
+        assert_eq!(rgb_to_ascii_char(&[101, 101, 101], &opts), 'f');
+        assert_eq!(rgb_to_ascii_char(&[255, 255, 255], &opts), '$');
+    }
+
+    #[test]
+    fn rich_cells_vary_across_a_gradient() {
+        // 64x8 horizontal gradient, 16 cells wide.
+        let row: Vec<u8> = (0..64u8).flat_map(|x| [x * 4; 3]).collect();
+        let image = row.repeat(8);
+        let opts = AsciiOptions {
+            width: 16,
+            rich: true,
+            ..Default::default()
+        };
+        let frame = convert_rgb(&image, 64, 8, &opts);
+        let line = frame.lines().next().unwrap();
+        let cells: Vec<(char, char)> = line
+            .chars()
+            .collect::<Vec<_>>()
+            .chunks(2)
+            .map(|cell| (cell[0], cell[1]))
+            .collect();
+        assert_eq!(cells.len(), 16);
+        let distinct: std::collections::HashSet<_> = cells.iter().collect();
+        assert!(distinct.len() >= 8, "{:?}", cells);
+        assert!(cells.iter().all(|(_, shade)| SHADES.contains(shade)));
+        assert_eq!(cells[0], (',', ' '));
+        assert_eq!(cells[15].1, '█');
+    }
+
+    #[test]
+    fn rich_textured_blocks_repeat_the_glyph() {
+        // 8x4 checkerboard, 2 cells wide.
+        let image: Vec<u8> = (0..32)
+            .flat_map(|i| [if (i % 8 + i / 8) % 2 == 0 { 0 } else { 255 }; 3])
+            .collect();
+        let opts = AsciiOptions {
+            width: 2,
+            rich: true,
+            ..Default::default()
+        };
+        let frame = convert_rgb(&image, 8, 4, &opts);
+        let cells: Vec<char> = frame.lines().next().unwrap().chars().collect();
+        assert_eq!(cells.len(), 4);
+        assert_eq!(cells[0], cells[1]);
+        assert!(!SHADES.contains(&cells[1]));
+    }
+
+    #[test]
+    fn luma_grid_midpoint_is_mid_ramp() {
+        let black = LumaGrid::from_rgb(
+            &[0; 12],
+            6,
+            (1, 2),
+            SampleOffset::TopLeft,
+            LumaWeights::Rec709,
+        );
+        let white = LumaGrid::from_rgb(
+            &[255; 12],
+            6,
+            (1, 2),
+            SampleOffset::TopLeft,
+            LumaWeights::Rec709,
+        );
+        let opts = AsciiOptions::default();
+        let midpoint = black.lerp(&white, 0.5);
+
+        assert_eq!(midpoint.cells.len(), 2);
+        assert!(midpoint.cells.iter().all(|lum| (lum - 127.5).abs() < 0.01));
+        let index_of = |frame: String| CHAR_MAP.find(frame.chars().next().unwrap()).unwrap();
+        let (dark, mid, bright) = (
+            index_of(black.to_ascii(&opts)),
+            index_of(midpoint.to_ascii(&opts)),
+            index_of(white.to_ascii(&opts)),
+        );
+        assert!(dark < mid && mid < bright);
+        assert_eq!(mid, (dark + bright) / 2);
+        assert_eq!(midpoint.to_ascii(&opts), "vv\n");
+    }
+
+    #[test]
+    fn rgb_to_palette_picks_nearest_gameboy_shade() {
+        let gameboy = Palette::Gameboy.colors();
+        let cases = [
+            ((0, 0, 0), (15, 56, 15)),
+            ((255, 255, 255), (155, 188, 15)),
+            ((100, 130, 40), (48, 98, 48)),
+            ((140, 170, 20), (139, 172, 15)),
+        ];
+        for (rgb, expected) in cases {
+            assert_eq!(rgb_to_palette(rgb, gameboy), expected, "{:?}", rgb);
+        }
+    }
+
+    #[test]
+    fn palette_quantizes_color_escapes() {
+        let opts = AsciiOptions {
+            width: 2,
+            color: ColorMode::TrueColor,
+            palette: Some(Palette::Gameboy),
+            ..Default::default()
+        };
+        assert_eq!(
+            convert_rgb(&split_image(), 4, 2, &opts),
+            "\x1B[38;2;155;188;15m$\x1B[38;2;15;56;15m \x1B[0m\n"
+        );
+    }
+
+    #[test]
+    fn rgb_to_ansi256_picks_the_nearest_cube_or_gray() {
+        let cases = [
+            ((0, 0, 0), 16),
+            ((255, 255, 255), 231),
+            ((255, 0, 0), 196),
+            ((100, 140, 210), 68),
+            ((128, 128, 128), 244),
+            ((12, 12, 14), 232),
+            ((250, 250, 250), 231),
+        ];
+        for (rgb, expected) in cases {
+            assert_eq!(rgb_to_ansi256(rgb), expected, "{:?}", rgb);
+        }
+    }
+
+    #[test]
+    fn ansi256_emits_palette_indices() {
+        let opts = AsciiOptions {
+            width: 2,
+            color: ColorMode::Ansi256,
+            ..Default::default()
+        };
+        assert_eq!(
+            convert_rgb(&split_image(), 4, 2, &opts),
+            "\x1B[38;5;231m$\x1B[38;5;16m \x1B[0m\n"
+        );
+    }
+
+    #[test]
+    fn center_offset_samples_the_middle_of_the_block() {
+        // 3x3 black image with a white center pixel, a single cell at width 1.
+        let mut rgb = vec![0u8; 27];
+        rgb[12..15].copy_from_slice(&[255; 3]);
+        let mut opts = AsciiOptions {
+            width: 1,
+            ..Default::default()
+        };
+        assert_eq!(convert_rgb(&rgb, 3, 3, &opts), " \n");
+        opts.sample_offset = SampleOffset::Center;
+        assert_eq!(convert_rgb(&rgb, 3, 3, &opts), "$\n");
+    }
+
+    #[test]
+    fn halfblock_stacks_two_pixels_per_cell() {
+        // A column of red, blue and green pixels.
+        let rgb = [255, 0, 0, 0, 0, 255, 0, 255, 0];
+        let opts = AsciiOptions {
+            width: 1,
+            halfblock: true,
+            color: ColorMode::TrueColor,
+            ..Default::default()
+        };
+        let frame = convert_rgb(&rgb, 1, 3, &opts);
+        assert_eq!(
+            frame,
+            "\x1B[38;2;255;0;0m\x1B[48;2;0;0;255m▀\x1B[0m\n\x1B[38;2;0;255;0m▀\x1B[0m\n"
+        );
+        assert!(frame.len() <= frame_capacity(1, 2, &opts));
+    }
+
+    #[test]
+    fn edges_follow_the_gradient_direction() {
+        // 4x4 grids, `#` cells white.
+        let cases = [
+            (["..##", "..##", "..##", "..##"], "|"),
+            (["....", "....", "####", "####"], "-"),
+            (["###.", "##..", "#...", "...."], "/"),
+            (["#...", "##..", "###.", "####"], "\\"),
+            (["....", "....", "....", "...."], " "),
+        ];
+        let opts = AsciiOptions {
+            edges: true,
+            cell_aspect: 1.0,
+            ..Default::default()
+        };
+        for (bitmap, glyph) in cases {
+            let cells = bitmap
+                .iter()
+                .flat_map(|row| row.chars())
+                .map(|c| if c == '#' { 255.0 } else { 0.0 })
+                .collect();
+            let frame = LumaGrid { cols: 4, cells }.to_ascii(&opts);
+            // The cell left of the middle, next to the boundary in every case.
+            let cell = &frame.lines().nth(1).unwrap()[1..2];
+            assert_eq!(cell, glyph, "{:?} drew\n{}", bitmap, frame);
+        }
+    }
+
+    #[test]
+    fn edges_handle_grids_without_neighbors() {
+        let opts = AsciiOptions {
+            edges: true,
+            ..Default::default()
+        };
+        let single = LumaGrid {
+            cols: 1,
+            cells: vec![255.0],
+        };
+        assert_eq!(single.to_ascii(&opts), " \n");
+        let row = LumaGrid {
+            cols: 3,
+            cells: vec![0.0, 0.0, 255.0],
+        };
+        assert_eq!(row.to_ascii(&opts), " ||\n");
+        assert_eq!(convert_rgb(&split_image(), 4, 2, &opts).lines().count(), 1);
+    }
+
+    #[test]
+    fn braille_dots_follow_the_bitmap() {
+        // One 2x4 cell per image, `#` pixels white.
+        let cases = [
+            (["#.", "#.", "#.", "#."], '\u{2847}'),
+            (["##", "..", "..", ".."], '\u{2809}'),
+            ([".#", "..", "..", ".#"], '\u{2888}'),
+            (["..", "..", "..", "##"], '\u{28C0}'),
+            (["##", "##", "##", "##"], '\u{28FF}'),
+            (["..", "..", "..", ".."], '\u{2800}'),
+        ];
+        let opts = AsciiOptions {
+            width: 1,
+            braille: true,
+            ..Default::default()
+        };
+        for (bitmap, expected) in cases {
+            let rgb: Vec<u8> = bitmap
+                .iter()
+                .flat_map(|row| row.chars())
+                .flat_map(|c| [if c == '#' { 255 } else { 0 }; 3])
+                .collect();
+            let frame = convert_rgb(&rgb, 2, 4, &opts);
+            assert_eq!(frame, format!("{}\n", expected), "{:?}", bitmap);
+        }
+    }
+
+    #[test]
+    fn braille_invert_lowers_the_bright_dots() {
+        let opts = AsciiOptions {
+            width: 1,
+            braille: true,
+            invert: true,
+            ..Default::default()
+        };
+        let rgb: Vec<u8> = (0..8)
+            .flat_map(|i| [if i % 2 == 0 { 255 } else { 0 }; 3])
+            .collect();
+        assert_eq!(convert_rgb(&rgb, 2, 4, &opts), "\u{28B8}\n");
+    }
+
+    #[test]
+    fn braille_frames_fit_their_reserved_capacity() {
+        let opts = AsciiOptions {
+            width: 8,
+            charset: "⠀⠁⠃⠇⠏⠟⠿⡿⣿".chars().collect(),
+            ..Default::default()
+        };
+        let rgb: Vec<u8> = (0..16 * 16).flat_map(|i| [i as u8; 3]).collect();
+        // (2, 4) sampling.
+        let reserved = frame_capacity(8, 4, &opts);
+        let frame = convert_rgb(&rgb, 16, 16, &opts);
+        assert!(frame.len() > 8 * 2, "glyphs should be multi-byte");
+        assert!(reserved >= frame.len());
+        assert_eq!(frame.capacity(), reserved, "buffer was reallocated");
+    }
+
+    #[test]
+    fn sampling_rate_compensates_for_tall_cells() {
+        assert_eq!(sampling_rate(1920, 72, 2.0), (27, 54));
+        assert_eq!(sampling_rate(640, 80, 2.0), (8, 16));
+        assert_eq!(sampling_rate(640, 80, 1.0), (8, 8));
+        assert_eq!(sampling_rate(640, 80, 2.3), (8, 18));
+        assert_eq!(sampling_rate(64, 200, 0.1), (1, 1));
+    }
+}
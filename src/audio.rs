@@ -0,0 +1,351 @@
+//! Soundtrack playback, behind the `audio` feature. The soundtrack is decoded on its own thread
+//! and played on the default output device, and its position is the clock video frames are
+//! paced by.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use video_rs::ffmpeg::{
+    self, codec, format, frame, media, software::resampling, ChannelLayout, Packet,
+};
+
+/// How much decoded audio is buffered ahead of the device.
+const BUFFER_AHEAD: Duration = Duration::from_millis(500);
+/// How long the decoder waits for room in the buffer before checking for seeks.
+const WAIT: Duration = Duration::from_millis(20);
+
+/// Samples waiting to be played, interleaved stereo at the device's rate.
+struct Buffer {
+    samples: VecDeque<f32>,
+    /// Stream position of the front sample, unknown until the first frame after a seek.
+    position: Option<Duration>,
+    /// The decoder reached the end of the soundtrack.
+    finished: bool,
+    /// Where the decoder should seek to before decoding on.
+    seek: Option<Duration>,
+    /// Bumped by every seek, so frames decoded before one can be told apart.
+    epoch: usize,
+}
+
+struct Shared {
+    buffer: Mutex<Buffer>,
+    /// Signalled whenever the device takes samples out of the buffer.
+    drained: Condvar,
+    paused: AtomicBool,
+    stop: AtomicBool,
+}
+
+impl Shared {
+    fn new() -> Shared {
+        Shared {
+            buffer: Mutex::new(Buffer {
+                samples: VecDeque::new(),
+                position: None,
+                finished: false,
+                seek: None,
+                epoch: 0,
+            }),
+            drained: Condvar::new(),
+            paused: AtomicBool::new(false),
+            stop: AtomicBool::new(false),
+        }
+    }
+}
+
+/// The soundtrack of the file being played, playing until dropped.
+pub struct Audio {
+    shared: Arc<Shared>,
+    decoder: Option<JoinHandle<()>>,
+    _stream: cpal::Stream,
+}
+
+impl Audio {
+    /// Starts playing the soundtrack of `file_name`. `None` if it has none, or it can't be played,
+    /// in which case playback is silent and paced by the wall clock.
+    pub fn start(file_name: &str) -> Option<Audio> {
+        let input = format::input(file_name).ok()?;
+        let stream = input.streams().best(media::Type::Audio)?;
+        let index = stream.index();
+        let time_base = stream.time_base();
+        let context = codec::context::Context::from_parameters(stream.parameters()).ok()?;
+        let decoder = match context.decoder().audio() {
+            Ok(decoder) => decoder,
+            Err(e) => return disabled(e),
+        };
+
+        let device = cpal::default_host().default_output_device()?;
+        let output = match device.default_output_config() {
+            Ok(output) => output,
+            Err(e) => return disabled(e),
+        };
+        let rate = output.sample_rate().0;
+        let channels = output.channels() as usize;
+        let resampler = match decoder.resampler(
+            format::Sample::F32(format::sample::Type::Packed),
+            ChannelLayout::STEREO,
+            rate,
+        ) {
+            Ok(resampler) => resampler,
+            Err(e) => return disabled(e),
+        };
+
+        let shared = Arc::new(Shared::new());
+        let playing = Arc::clone(&shared);
+        let stream = device.build_output_stream(
+            &output.config(),
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                fill(data, channels, rate, &playing)
+            },
+            |e| eprintln!("Audio output failed: {}", e),
+            None,
+        );
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => return disabled(e),
+        };
+        if let Err(e) = stream.play() {
+            return disabled(e);
+        }
+
+        let decoding = Arc::clone(&shared);
+        let soundtrack = Soundtrack {
+            input,
+            index,
+            time_base,
+            decoder,
+            resampler,
+            rate,
+        };
+        let decoder = std::thread::spawn(move || {
+            if let Err(e) = soundtrack.decode(&decoding) {
+                eprintln!("Failed to decode audio, carrying on without it: {}", e);
+            }
+            decoding.buffer.lock().unwrap().finished = true;
+        });
+        Some(Audio {
+            shared,
+            decoder: Some(decoder),
+            _stream: stream,
+        })
+    }
+
+    /// Stream position of what's being heard, `None` once the soundtrack is over.
+    pub fn position(&self) -> Option<Duration> {
+        let buffer = self.shared.buffer.lock().unwrap();
+        match buffer.finished && buffer.samples.is_empty() {
+            true => None,
+            false => buffer.position,
+        }
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.shared.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Moves the soundtrack to `target`, the clock is unknown until it gets there.
+    pub fn seek(&self, target: Duration) {
+        let mut buffer = self.shared.buffer.lock().unwrap();
+        buffer.samples.clear();
+        buffer.position = None;
+        buffer.finished = false;
+        buffer.seek = Some(target);
+        buffer.epoch += 1;
+        self.shared.drained.notify_one();
+    }
+}
+
+impl Drop for Audio {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        self.shared.drained.notify_one();
+        if let Some(decoder) = self.decoder.take() {
+            let _ = decoder.join();
+        }
+    }
+}
+
+fn disabled(e: impl std::fmt::Display) -> Option<Audio> {
+    eprintln!("Cannot play audio, playing without it: {}", e);
+    None
+}
+
+/// Everything the decoder thread needs, moved there as a whole.
+struct Soundtrack {
+    input: format::context::Input,
+    index: usize,
+    time_base: ffmpeg::Rational,
+    decoder: codec::decoder::Audio,
+    resampler: resampling::Context,
+    rate: u32,
+}
+
+impl Soundtrack {
+    /// Decodes into the shared buffer until stopped, waiting at the end for a seek back.
+    fn decode(mut self, shared: &Shared) -> Result<(), ffmpeg::Error> {
+        let mut at_end = false;
+        while !shared.stop.load(Ordering::Relaxed) {
+            let (seek, epoch) = {
+                let mut buffer = shared.buffer.lock().unwrap();
+                (buffer.seek.take(), buffer.epoch)
+            };
+            if let Some(target) = seek {
+                // AV_TIME_BASE is microseconds.
+                let timestamp = target.as_micros() as i64;
+                self.input.seek(timestamp, ..timestamp)?;
+                self.decoder.flush();
+                at_end = false;
+            }
+            if at_end {
+                std::thread::sleep(WAIT);
+                continue;
+            }
+            let mut packet = Packet::empty();
+            match packet.read(&mut self.input) {
+                Ok(()) => {}
+                Err(ffmpeg::Error::Eof) => {
+                    let mut buffer = shared.buffer.lock().unwrap();
+                    buffer.finished = buffer.epoch == epoch;
+                    at_end = true;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            }
+            if packet.stream() != self.index {
+                continue;
+            }
+            self.decoder.send_packet(&packet)?;
+            let mut decoded = frame::Audio::empty();
+            while self.decoder.receive_frame(&mut decoded).is_ok() {
+                // Room for everything, upsampling included.
+                let capacity =
+                    decoded.samples() * self.rate as usize / decoded.rate().max(1) as usize;
+                let mut resampled = frame::Audio::new(
+                    format::Sample::F32(format::sample::Type::Packed),
+                    capacity + 64,
+                    ChannelLayout::STEREO,
+                );
+                self.resampler.run(&decoded, &mut resampled)?;
+                let position = decoded.timestamp().map(|timestamp| {
+                    let secs = timestamp as f64 * self.time_base.numerator() as f64
+                        / self.time_base.denominator() as f64;
+                    Duration::try_from_secs_f64(secs).unwrap_or_default()
+                });
+                self.push(shared, epoch, resampled.plane::<(f32, f32)>(0), position);
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends `frames`, decoded in `epoch`, to the buffer once there's room for them.
+    fn push(
+        &self,
+        shared: &Shared,
+        epoch: usize,
+        frames: &[(f32, f32)],
+        position: Option<Duration>,
+    ) {
+        let limit = (BUFFER_AHEAD.as_secs_f64() * self.rate as f64) as usize * 2;
+        let mut buffer = shared.buffer.lock().unwrap();
+        while buffer.samples.len() >= limit
+            && buffer.epoch == epoch
+            && !shared.stop.load(Ordering::Relaxed)
+        {
+            buffer = shared.drained.wait_timeout(buffer, WAIT).unwrap().0;
+        }
+        // From before a seek, the next round picks the seek up.
+        if buffer.epoch != epoch {
+            return;
+        }
+        if buffer.position.is_none() && buffer.samples.is_empty() {
+            buffer.position = position;
+        }
+        buffer
+            .samples
+            .extend(frames.iter().flat_map(|&(l, r)| [l, r]));
+    }
+}
+
+/// Device callback: hands over buffered samples, or silence when paused or starved.
+fn fill(data: &mut [f32], channels: usize, rate: u32, shared: &Shared) {
+    data.fill(0.0);
+    if shared.paused.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut buffer = shared.buffer.lock().unwrap();
+    let mut played = 0;
+    for out in data.chunks_mut(channels) {
+        if buffer.samples.len() < 2 {
+            break;
+        }
+        let (l, r) = (
+            buffer.samples.pop_front().unwrap(),
+            buffer.samples.pop_front().unwrap(),
+        );
+        match out {
+            [mono] => *mono = (l + r) / 2.0,
+            [left, right, ..] => (*left, *right) = (l, r),
+            [] => {}
+        }
+        played += 1;
+    }
+    if let Some(position) = &mut buffer.position {
+        *position += Duration::from_secs_f64(played as f64 / rate as f64);
+    }
+    shared.drained.notify_one();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shared_with(samples: &[f32]) -> Shared {
+        let shared = Shared::new();
+        let mut buffer = shared.buffer.lock().unwrap();
+        buffer.samples.extend(samples);
+        buffer.position = Some(Duration::from_secs(1));
+        drop(buffer);
+        shared
+    }
+
+    #[test]
+    fn fill_advances_the_clock_by_what_was_played() {
+        let shared = shared_with(&[0.1, 0.2, 0.3, 0.4, 0.5, 0.6]);
+        let mut data = [1.0; 8];
+        fill(&mut data, 2, 4, &shared);
+        assert_eq!(data, [0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.0, 0.0]);
+        let buffer = shared.buffer.lock().unwrap();
+        assert!(buffer.samples.is_empty());
+        assert_eq!(buffer.position, Some(Duration::from_millis(1750)));
+    }
+
+    #[test]
+    fn fill_mixes_down_and_pads_channels() {
+        let shared = shared_with(&[0.2, 0.4, 0.6, 0.8]);
+        let mut mono = [0.0; 1];
+        fill(&mut mono, 1, 48_000, &shared);
+        assert!((mono[0] - 0.3).abs() < 1e-6);
+        let mut surround = [1.0; 4];
+        fill(&mut surround, 4, 48_000, &shared);
+        assert_eq!(surround, [0.6, 0.8, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn fill_is_silent_and_still_while_paused() {
+        let shared = shared_with(&[0.5, 0.5]);
+        shared.paused.store(true, Ordering::Relaxed);
+        let mut data = [1.0; 2];
+        fill(&mut data, 2, 48_000, &shared);
+        assert_eq!(data, [0.0, 0.0]);
+        let buffer = shared.buffer.lock().unwrap();
+        assert_eq!(buffer.samples.len(), 2);
+        assert_eq!(buffer.position, Some(Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,91 @@
+//! Frames written to disk with `--output`, instead of animated in the terminal.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// Separates frames exported to a single file, `less` and `pr` page by it.
+pub const FORM_FEED: u8 = b'\x0C';
+
+/// Writer that takes every flush of the player as the end of a frame, and files frames away
+/// one by one.
+pub struct Frames {
+    target: Target,
+    frame: Vec<u8>,
+    written: usize,
+}
+
+enum Target {
+    /// One file, frames separated by form feeds.
+    File(BufWriter<File>),
+    /// A directory holding a numbered `.txt` file per frame.
+    Directory(PathBuf),
+}
+
+impl Frames {
+    /// A directory of frame files if `path` is one or ends with a separator, created if need
+    /// be, otherwise a single file.
+    pub fn create(path: &Path) -> io::Result<Frames> {
+        let directory = path.is_dir() || path.as_os_str().to_string_lossy().ends_with(['/', '\\']);
+        let target = if directory {
+            fs::create_dir_all(path)?;
+            Target::Directory(path.to_path_buf())
+        } else {
+            Target::File(BufWriter::new(File::create(path)?))
+        };
+        Ok(Frames {
+            target,
+            frame: Vec::new(),
+            written: 0,
+        })
+    }
+
+    fn frame_path(dir: &Path, index: usize) -> PathBuf {
+        dir.join(format!("{:06}.txt", index))
+    }
+}
+
+impl Write for Frames {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.frame.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.frame.is_empty() {
+            return Ok(());
+        }
+        // Without a single full line it's no frame, but a tail like the color reset after the
+        // last one, and belongs to the frame before.
+        let tail = self.written > 0 && !self.frame.contains(&b'\n');
+        match &mut self.target {
+            Target::File(file) => {
+                if self.written > 0 && !tail {
+                    file.write_all(&[FORM_FEED])?;
+                }
+                file.write_all(&self.frame)?;
+                file.flush()?;
+            }
+            Target::Directory(dir) => {
+                let path = match tail {
+                    true => Frames::frame_path(dir, self.written - 1),
+                    false => Frames::frame_path(dir, self.written),
+                };
+                OpenOptions::new()
+                    .create(true)
+                    .append(tail)
+                    .write(true)
+                    .truncate(!tail)
+                    .open(path)?
+                    .write_all(&self.frame)?;
+            }
+        }
+        if !tail {
+            self.written += 1;
+        }
+        self.frame.clear();
+        Ok(())
+    }
+}
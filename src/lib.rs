@@ -1,273 +1,1365 @@
-use std::{
-    collections::VecDeque,
-    io::{self, Write},
-    process::exit,
-    sync::{mpsc, Arc, Condvar, Mutex},
-    time::{Duration, Instant},
-};
+use std::{fmt, time::Duration};
+#[cfg(not(target_arch = "wasm32"))]
 use video_rs::Decoder;
 
+pub mod ascii;
+#[cfg(feature = "audio")]
+mod audio;
+#[cfg(not(target_arch = "wasm32"))]
+mod export;
+#[cfg(not(target_arch = "wasm32"))]
+mod player;
+pub mod subtitles;
+mod term;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use player::{Command, FrameStats, PlaybackSummary, Player, PlayerError};
+
+use ascii::AsciiOptions;
+
+pub use globals::{get_sample_mp4, NETWORK_TIMEOUT, STDIN};
+
 mod globals {
-    pub const ROOT_DIR: &str = "/home/klaudiusz/Desktop/Projects/rust/terminal_player/";
     pub const SAMPLE_DIR: &str = "samples/";
+    /// Overrides where the sample played when no file is given.
+    pub const SAMPLE_ENV: &str = "TERMINAL_PLAYER_SAMPLE";
+    /// File name standing for the video piped to stdin.
+    pub const STDIN: &str = "-";
+    /// Protocols a file name is opened as a network stream for.
+    pub const URL_SCHEMES: [&str; 3] = ["http", "https", "rtsp"];
+    /// How long a network stream may go silent, connecting included, before giving up on it.
+    pub const NETWORK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
     pub const DEF_WIDTH: usize = 72;
     pub const FRAME_BACKLOG: usize = 30 * 10;
+    pub const KEYFRAME_PREVIEW_FPS: u64 = 2;
+    pub const DEF_FPS: f32 = 30.0;
+    pub const DEF_MAX_FPS: f32 = 120.0;
+    pub const DEF_CELL_ASPECT: f32 = 2.0;
+    /// Most threads converting frames to text, next to the one decoding them.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub const MAX_CONVERTERS: usize = 4;
+    /// Widest output picked on its own, however wide the terminal.
+    pub const MAX_AUTO_WIDTH: usize = 400;
+    /// Room for the longest status line without a progress bar, `h:mm:ss / h:mm:ss`.
+    pub const HUD_MIN_WIDTH: usize = 24;
+    /// The sample video, `$TERMINAL_PLAYER_SAMPLE` or the one shipped in the crate directory.
     pub fn get_sample_mp4() -> String {
-        format!("{}{}sample.mp4", ROOT_DIR, SAMPLE_DIR)
+        std::env::var(SAMPLE_ENV)
+            .unwrap_or_else(|_| format!("{}/{}sample.mp4", env!("CARGO_MANIFEST_DIR"), SAMPLE_DIR))
     }
 }
 #[derive(Debug, Clone)]
 pub struct Config {
     pub file_name: String,
     pub video_size: (usize, usize),
-    pub width_chars: usize,
     pub sampling_rate: (usize, usize),
     pub aspect_ratio: f32,
+    /// Rate frames are rendered at, at most `max_fps`.
     pub frame_rate: u64,
+    /// Rate the stream was encoded at, for timestamp based pacing.
+    pub source_frame_rate: f32,
+    pub max_fps: f32,
+    /// Playback speed multiplier, 2.0 plays twice as fast.
+    pub speed: f32,
+    /// Only every `frame_stride`th decoded frame is rendered, to stay under `max_fps`.
+    pub frame_stride: usize,
+    /// Render rate asked for with `--interpolate`.
+    pub interpolate_fps: Option<f32>,
+    /// Frames shown per decoded frame, all but the last blended from the previous one.
+    pub interpolation_steps: usize,
     pub frame_size: usize,
     pub delta_t_ms: Duration,
+    /// Length of the stream, if the container reports one.
+    pub duration: Option<Duration>,
+    pub keyframes_only: bool,
+    /// Start paused, to go through frames one at a time.
+    pub step: bool,
+    /// Play without the soundtrack, with the `audio` feature.
+    pub mute: bool,
+    /// Start over once the stream ends.
+    pub loop_playback: bool,
+    /// Passes to play when looping, forever if `None`.
+    pub loop_count: Option<usize>,
+    pub redraw_mode: RedrawMode,
+    /// Frames decoded ahead of playback, as a cushion against decoding stalls.
+    pub buffer_frames: usize,
+    /// Upper bound for the bytes held by the frame queue, on top of its frame count.
+    pub max_mem_bytes: Option<usize>,
+    pub emit_meta: bool,
+    /// Print the `PlaybackSummary` to stderr once playback is over.
+    pub stats: bool,
+    /// Show a status line with the timecode and a progress bar below the image.
+    pub hud: bool,
+    /// Cues from `--subs`, ordered by start time.
+    pub subtitles: Vec<subtitles::Cue>,
+    /// File or directory `run` writes frames to instead of the terminal.
+    pub output: Option<std::path::PathBuf>,
+    /// Pace frames by their timestamps, dropping some to keep up. Without it every frame is
+    /// written as soon as it's decoded, as when exporting.
+    pub realtime: bool,
+    /// Guarantee nothing but printable 7-bit ASCII and newlines is written.
+    pub ascii_only: bool,
+    pub ascii: AsciiOptions,
+    /// Width follows the terminal, as no `--width` was given.
+    pub auto_width: bool,
+    /// Most rows the image may take, from `--height`.
+    pub height: Option<usize>,
+    /// Which of width and height bound the output size.
+    pub fit: FitMode,
+    /// Columns and rows of the terminal, once detected.
+    pub terminal_size: Option<(usize, usize)>,
+}
+
+/// Which bounds the output is sized by. The aspect ratio is kept whichever it is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FitMode {
+    /// As wide as the width allows, however many rows that takes.
+    Width,
+    /// As many rows as the height allows, however wide that gets.
+    Height,
+    /// The largest size within both, so the one that binds first wins and the other dimension
+    /// comes out short. An explicit width and height letterbox the image rather than stretch it.
+    #[default]
+    Contain,
+}
+
+impl std::str::FromStr for FitMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "width" => Ok(FitMode::Width),
+            "height" => Ok(FitMode::Height),
+            "contain" => Ok(FitMode::Contain),
+            _ => Err(format!("Unknown fit mode {}", s)),
+        }
+    }
 }
 
+/// How the previous frame is replaced by the next one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RedrawMode {
+    /// Clear the whole screen before every frame.
+    Clear,
+    /// Clear the screen once, then only move the cursor to the top-left corner and overwrite in
+    /// place. Flicker free, rows are cleared to their end and the screen below the frame, so a
+    /// smaller frame leaves nothing of the previous one behind.
+    #[default]
+    Home,
+    /// Return to the start of the line, for single-row output such as banners.
+    CarriageReturn,
+    /// Print frames one after another, for pipes and logs.
+    Append,
+}
+
+impl std::str::FromStr for RedrawMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clear" => Ok(RedrawMode::Clear),
+            "home" => Ok(RedrawMode::Home),
+            "cr" => Ok(RedrawMode::CarriageReturn),
+            "append" => Ok(RedrawMode::Append),
+            _ => Err(format!("Unknown redraw mode {}", s)),
+        }
+    }
+}
+
+/// Why the command line couldn't be turned into a `Config`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    UnknownFlag(String),
+    /// The flag was last and its value is missing.
+    MissingValue(String),
+    InvalidValue {
+        flag: String,
+        value: String,
+    },
+    MissingFile,
+    /// Options that contradict each other, with the reason.
+    Conflict(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::UnknownFlag(flag) => write!(f, "Unknown flag: {}", flag),
+            ConfigError::MissingValue(flag) => write!(f, "Missing value for flag \"{}\".", flag),
+            ConfigError::InvalidValue { flag, value } => {
+                write!(f, "Invalid value {} for flag \"{}\".", value, flag)
+            }
+            ConfigError::MissingFile => write!(f, "Provide a path to the file."),
+            ConfigError::Conflict(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
 impl Config {
-    pub fn from_args(args: &[String]) -> Result<Config, String> {
-        //TODO: Look for -w flag for width
-        let mut width = globals::DEF_WIDTH;
+    pub fn from_args(args: &[String]) -> Result<Config, ConfigError> {
         let mut file_name = globals::get_sample_mp4();
-        for (i, arg) in args.iter().enumerate() {
+        let mut keyframes_only = false;
+        let mut step = false;
+        let mut mute = false;
+        let mut redraw_mode = None;
+        let mut max_mem_bytes = None;
+        let mut buffer_frames = globals::FRAME_BACKLOG;
+        let mut emit_meta = false;
+        let mut stats = false;
+        let mut hud = true;
+        let mut subtitles = Vec::new();
+        let mut output = None;
+        let mut ascii_only = false;
+        let mut max_fps = globals::DEF_MAX_FPS;
+        let mut speed = 1.0;
+        let mut interpolate_fps = None;
+        let mut loop_playback = false;
+        let mut loop_count = None;
+        let mut ascii = AsciiOptions::default();
+        let mut auto_width = true;
+        let mut height = None;
+        let mut fit = FitMode::default();
+        // `--flag=value` is the same as `--flag value`.
+        let split_args: Vec<String> = args
+            .iter()
+            .skip(1)
+            .flat_map(|arg| match arg.split_once('=') {
+                Some((flag, value)) if arg.starts_with("--") || flag == "-w" => {
+                    vec![flag.to_string(), value.to_string()]
+                }
+                _ => vec![arg.clone()],
+            })
+            .collect();
+        let mut args_iter = split_args.iter().peekable();
+        while let Some(arg) = args_iter.next() {
             match arg {
                 arg if arg.starts_with('-') => match arg {
-                    arg if arg.starts_with("--width") || arg.starts_with("-w") => {
-                        width = args[i + 1].parse().unwrap_or_else(|_| {
-                            panic!("Invalid value {} for flag \"width\".", args[i + 1])
-                        });
+                    arg if arg == globals::STDIN || arg == "--stdin" => {
+                        file_name = String::from(globals::STDIN);
+                    }
+                    arg if arg == "--width" || arg == "-w" => {
+                        ascii.width = flag_value(&mut args_iter, "width")?;
+                        auto_width = false;
+                    }
+                    arg if arg == "--height" => {
+                        let rows: usize = flag_value(&mut args_iter, "height")?;
+                        if rows == 0 {
+                            return Err(ConfigError::InvalidValue {
+                                flag: String::from("height"),
+                                value: rows.to_string(),
+                            });
+                        }
+                        height = Some(rows);
+                    }
+                    arg if arg == "--fit" => fit = flag_value(&mut args_iter, "fit")?,
+                    arg if arg == "--keyframes-only" => keyframes_only = true,
+                    arg if arg == "--step" => step = true,
+                    arg if arg == "--mute" => mute = true,
+                    arg if arg == "--loop" => {
+                        loop_playback = true;
+                        // The count is optional, anything else is left for the next round.
+                        if let Some(count) = args_iter.peek().and_then(|next| next.parse().ok()) {
+                            loop_count = Some(count);
+                            args_iter.next();
+                        }
+                    }
+                    arg if arg == "--no-clear" => redraw_mode = Some(RedrawMode::Append),
+                    arg if arg == "--redraw-mode" => {
+                        redraw_mode = Some(flag_value(&mut args_iter, "redraw-mode")?);
+                    }
+                    arg if arg == "--buffer" => {
+                        buffer_frames = flag_value(&mut args_iter, "buffer")?;
+                        if buffer_frames == 0 {
+                            return Err(ConfigError::InvalidValue {
+                                flag: String::from("buffer"),
+                                value: buffer_frames.to_string(),
+                            });
+                        }
+                    }
+                    arg if arg == "--max-mem" => {
+                        let megabytes: usize = flag_value(&mut args_iter, "max-mem")?;
+                        let bytes = megabytes.checked_mul(1024 * 1024).ok_or_else(|| {
+                            ConfigError::InvalidValue {
+                                flag: String::from("max-mem"),
+                                value: megabytes.to_string(),
+                            }
+                        })?;
+                        max_mem_bytes = Some(bytes);
+                    }
+                    arg if arg == "--emit-meta" => emit_meta = true,
+                    arg if arg == "--stats" => stats = true,
+                    arg if arg == "--output" || arg == "-o" => {
+                        output = Some(flag_value::<String>(&mut args_iter, "output")?.into());
+                    }
+                    arg if arg == "--no-hud" => hud = false,
+                    arg if arg == "--subs" => {
+                        let path: String = flag_value(&mut args_iter, "subs")?;
+                        let srt = std::fs::read_to_string(&path).map_err(|_| {
+                            ConfigError::InvalidValue {
+                                flag: String::from("subs"),
+                                value: path,
+                            }
+                        })?;
+                        subtitles = subtitles::parse_srt(&srt);
+                    }
+                    arg if arg == "--max-fps" => max_fps = flag_value(&mut args_iter, "max-fps")?,
+                    arg if arg == "--speed" || arg == "-s" => {
+                        speed = positive_flag_value(&mut args_iter, "speed")?;
+                    }
+                    arg if arg == "--interpolate" => {
+                        interpolate_fps = Some(flag_value(&mut args_iter, "interpolate")?);
+                    }
+                    arg if arg == "--threshold" => {
+                        ascii.threshold = Some(flag_value(&mut args_iter, "threshold")?);
+                    }
+                    arg if arg == "--color" => ascii.color = ascii::ColorMode::TrueColor,
+                    arg if arg == "--color256" => ascii.color = ascii::ColorMode::Ansi256,
+                    arg if arg == "--charset" => {
+                        let charset: String = flag_value(&mut args_iter, "charset")?;
+                        if charset.is_empty() {
+                            return Err(ConfigError::InvalidValue {
+                                flag: String::from("charset"),
+                                value: charset,
+                            });
+                        }
+                        ascii.charset = charset.chars().collect();
+                    }
+                    arg if arg == "--invert" => ascii.invert = true,
+                    arg if arg == "--gamma" => {
+                        ascii.gamma = positive_flag_value(&mut args_iter, "gamma")?;
                     }
-                    _ => {
-                        eprint!("Unknown flag: {}", arg);
-                        exit(1)
+                    arg if arg == "--luma" => ascii.luma = flag_value(&mut args_iter, "luma")?,
+                    arg if arg == "--rich" => ascii.rich = true,
+                    arg if arg == "--braille" => ascii.braille = true,
+                    arg if arg == "--edges" => ascii.edges = true,
+                    arg if arg == "--halfblock" => {
+                        ascii.halfblock = true;
+                        // 256 colors will do too, if asked for.
+                        if ascii.color == ascii::ColorMode::Mono {
+                            ascii.color = ascii::ColorMode::TrueColor;
+                        }
                     }
+                    arg if arg == "--cell-aspect" => {
+                        ascii.cell_aspect = positive_flag_value(&mut args_iter, "cell-aspect")?;
+                    }
+                    arg if arg == "--sample-offset" => {
+                        ascii.sample_offset = flag_value(&mut args_iter, "sample-offset")?;
+                    }
+                    arg if arg == "--palette" => {
+                        ascii.palette = Some(flag_value(&mut args_iter, "palette")?);
+                        // Quantized colors only make sense when colors are shown.
+                        if ascii.color == ascii::ColorMode::Mono {
+                            ascii.color = ascii::ColorMode::TrueColor;
+                        }
+                    }
+                    arg if arg == "--ascii-only" => ascii_only = true,
+                    _ => return Err(ConfigError::UnknownFlag(arg.clone())),
                 },
                 arg => file_name.clone_from(arg),
             }
         }
+        // Clearing and cursor movement are escape codes too, and files get frames only.
+        let redraw_mode = redraw_mode.unwrap_or(match ascii_only || output.is_some() {
+            true => RedrawMode::Append,
+            false => RedrawMode::default(),
+        });
+        let hud = hud && output.is_none();
         if args.len() > 1 {
-            Ok(Config {
+            let cfg = Config {
                 file_name,
                 video_size: (0, 0),
-                width_chars: width,
                 sampling_rate: (0, 0),
                 aspect_ratio: 0.0,
-                frame_rate: 30,
+                frame_rate: globals::DEF_FPS as u64,
+                source_frame_rate: globals::DEF_FPS,
+                max_fps,
+                speed,
+                frame_stride: 1,
+                interpolate_fps,
+                interpolation_steps: 1,
                 frame_size: 0,
                 delta_t_ms: Duration::from_millis(0),
-            })
+                duration: None,
+                keyframes_only,
+                step,
+                mute,
+                loop_playback,
+                loop_count,
+                redraw_mode,
+                buffer_frames,
+                max_mem_bytes,
+                emit_meta,
+                stats,
+                hud,
+                subtitles,
+                realtime: output.is_none(),
+                output,
+                ascii_only,
+                ascii,
+                auto_width,
+                height,
+                fit,
+                terminal_size: None,
+            };
+            cfg.validate()?;
+            Ok(cfg)
         } else {
-            Err(String::from("Provide a path to the file."))
+            Err(ConfigError::MissingFile)
         }
     }
 
+    /// Rejects option combinations that contradict each other.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.ascii_only {
+            let conflict = if self.ascii.halfblock {
+                Some("--halfblock")
+            } else if self.ascii.color != ascii::ColorMode::Mono {
+                Some("color output")
+            } else if self.ascii.rich {
+                Some("--rich")
+            } else if self.ascii.braille {
+                Some("--braille")
+            } else if self.redraw_mode != RedrawMode::Append {
+                Some("a --redraw-mode other than append")
+            } else if !self
+                .ascii
+                .charset
+                .iter()
+                .all(|c| c.is_ascii_graphic() || *c == ' ')
+            {
+                Some("a charset with non-ASCII or control characters")
+            } else if !self
+                .subtitles
+                .iter()
+                .flat_map(|cue| cue.lines.iter())
+                .all(|line| line.chars().all(|c| c.is_ascii_graphic() || c == ' '))
+            {
+                Some("subtitles with non-ASCII text")
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(ConfigError::Conflict(format!(
+                    "--ascii-only can't be combined with {}.",
+                    conflict
+                )));
+            }
+        }
+        if self.loop_playback && self.reads_stdin() {
+            return Err(ConfigError::Conflict(String::from(
+                "--loop can't be combined with reading stdin, a pipe can't be rewound.",
+            )));
+        }
+        if self.loop_playback && self.reads_network() {
+            return Err(ConfigError::Conflict(String::from(
+                "--loop can't be combined with a URL, a live stream can't be rewound.",
+            )));
+        }
+        if self.output.is_some() && self.redraw_mode != RedrawMode::Append {
+            return Err(ConfigError::Conflict(String::from(
+                "--output can't be combined with a --redraw-mode other than append.",
+            )));
+        }
+        if self.ascii.edges {
+            // Outlines are drawn from luminance alone, in glyphs of their own.
+            let conflict = if self.ascii.color != ascii::ColorMode::Mono {
+                Some("color output")
+            } else if self.ascii.rich {
+                Some("--rich")
+            } else if self.ascii.braille {
+                Some("--braille")
+            } else if self.ascii.halfblock {
+                Some("--halfblock")
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(ConfigError::Conflict(format!(
+                    "--edges can't be combined with {}.",
+                    conflict
+                )));
+            }
+        }
+        if self.ascii.halfblock {
+            // Each draws cells its own way, and interpolation blends mono glyphs.
+            let conflict = if self.ascii.rich {
+                Some("--rich")
+            } else if self.ascii.braille {
+                Some("--braille")
+            } else if self.interpolate_fps.is_some() {
+                Some("--interpolate")
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(ConfigError::Conflict(format!(
+                    "--halfblock can't be combined with {}.",
+                    conflict
+                )));
+            }
+        }
+        if self.interpolate_fps.is_some() {
+            // Frames are blended by luminance alone, one glyph a cell.
+            let conflict = if self.ascii.color != ascii::ColorMode::Mono {
+                Some("color output")
+            } else if self.ascii.rich {
+                Some("--rich")
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(ConfigError::Conflict(format!(
+                    "--interpolate can't be combined with {}.",
+                    conflict
+                )));
+            }
+        }
+        if self.ascii.braille {
+            // Both replace the glyph path braille is drawn by.
+            let conflict = if self.ascii.rich {
+                Some("--rich")
+            } else if self.interpolate_fps.is_some() {
+                Some("--interpolate")
+            } else {
+                None
+            };
+            if let Some(conflict) = conflict {
+                return Err(ConfigError::Conflict(format!(
+                    "--braille can't be combined with {}.",
+                    conflict
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the video is piped to stdin, which can be read only once, front to back.
+    pub fn reads_stdin(&self) -> bool {
+        self.file_name == globals::STDIN
+    }
+
+    /// Whether the video comes over the network, where the duration is often unknown and
+    /// seeking may not be possible.
+    pub fn reads_network(&self) -> bool {
+        is_url(&self.file_name)
+    }
+
+    /// Queries the terminal and, unless `--width` was given, fills its width.
+    pub fn detect_terminal_size(&mut self) {
+        self.fit_to_terminal(term::size());
+    }
+
+    /// Falls back to the default width when the size isn't known.
+    pub fn fit_to_terminal(&mut self, size: Option<(usize, usize)>) {
+        self.terminal_size = size;
+        let Some((cols, _)) = size else {
+            return;
+        };
+        if self.auto_width {
+            // Rich cells are two characters wide.
+            let cols = if self.ascii.rich { cols / 2 } else { cols };
+            self.ascii.width = cols.clamp(1, globals::MAX_AUTO_WIDTH);
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
     pub fn add_decoder_info(&mut self, decoder: &video_rs::Decoder) {
-        self.aspect_ratio = decoder.size().0 as f32 / decoder.size().1 as f32;
-        self.video_size = (decoder.size().0 as usize, decoder.size().1 as usize);
-        let sample_x = self.video_size.0 / self.width_chars;
-        self.sampling_rate = (sample_x, (sample_x as f32 / self.aspect_ratio) as usize);
-        self.frame_rate = decoder.frame_rate() as u64;
-        self.frame_size = ((self.width_chars ^ 2) as f32 * self.aspect_ratio) as usize;
-        self.delta_t_ms = Duration::from_millis((1000.0 / decoder.frame_rate()) as u64);
+        let (width, height) = decoder.size();
+        self.configure_for(width as usize, height as usize, decoder.frame_rate());
+        self.duration = decoder
+            .duration()
+            .ok()
+            .map(|duration| duration.as_secs_f64())
+            .filter(|secs| *secs > 0.0)
+            .map(Duration::from_secs_f64);
+    }
+
+    /// Derives sampling and pacing for a `width`x`height` source playing at `fps`.
+    pub fn configure_for(&mut self, width: usize, height: usize, fps: f32) {
+        self.aspect_ratio = width as f32 / height as f32;
+        self.video_size = (width, height);
+        if let (FitMode::Height, Some(_)) = (self.fit, self.max_rows()) {
+            // No wider than one column per pixel, narrowed down to the height below.
+            self.ascii.width = width.max(1);
+        }
+        self.sampling_rate =
+            ascii::sampling_rate(width, self.ascii.width, self.ascii.sample_aspect());
+        if let (FitMode::Height | FitMode::Contain, Some(max_rows)) = (self.fit, self.max_rows()) {
+            while self.output_size().1 > max_rows && self.ascii.width > 1 {
+                self.ascii.width -= 1;
+                self.sampling_rate =
+                    ascii::sampling_rate(width, self.ascii.width, self.ascii.sample_aspect());
+            }
+        }
+        let (cols, rows) = self.output_size();
+        self.frame_size = ascii::frame_capacity(cols, rows, &self.ascii);
+        self.frame_size += self.subtitle_rows() * (self.output_columns() + 1);
+        if self.hud_rows() > 0 {
+            // The timecodes alone can be wider than a narrow image.
+            self.frame_size += self.output_columns().max(globals::HUD_MIN_WIDTH) + 1;
+        }
+        // Some containers don't report a rate at all.
+        self.source_frame_rate = if fps > 0.0 { fps } else { globals::DEF_FPS };
+        let played_fps = self.source_frame_rate * self.speed;
+        self.frame_stride = if self.keyframes_only || self.max_fps <= 0.0 {
+            1
+        } else {
+            (played_fps / self.max_fps).ceil().max(1.0) as usize
+        };
+        let mut render_fps = played_fps / self.frame_stride as f32;
+        self.interpolation_steps = match self.interpolate_fps {
+            Some(target) if !self.keyframes_only && target > render_fps => {
+                (target.min(self.max_fps) / render_fps).round().max(1.0) as usize
+            }
+            _ => 1,
+        };
+        render_fps *= self.interpolation_steps as f32;
+        self.frame_rate = render_fps as u64;
+        // Not rounded to whole milliseconds, fast playback can get well below one.
+        self.delta_t_ms = Duration::from_secs_f64(1.0 / render_fps as f64);
+        if self.keyframes_only {
+            // Keyframes are sparse and irregular, pace them as a slideshow instead.
+            let slideshow_fps = globals::KEYFRAME_PREVIEW_FPS as f64 * self.speed as f64;
+            self.delta_t_ms = Duration::from_secs_f64(1.0 / slideshow_fps);
+        }
+    }
+
+    /// Shrinks an output too big for the terminal, as an explicit `--width` or `--height` can
+    /// ask for, since wrapped rows scramble every frame. Returns the columns and rows asked for
+    /// when they had to be cut down.
+    pub fn shrink_to_terminal(&mut self) -> Option<(usize, usize)> {
+        let (term_cols, term_rows) = self.terminal_size?;
+        let asked = (self.output_columns(), self.output_size().1);
+        let max_rows = match self.redraw_mode {
+            RedrawMode::Clear | RedrawMode::Home => {
+                let below = self.subtitle_rows() + self.hud_rows() + 1;
+                Some(term_rows.saturating_sub(below).max(1))
+            }
+            // Appended frames scroll by anyway, and a single row has nothing to fit.
+            RedrawMode::CarriageReturn | RedrawMode::Append => None,
+        };
+        if asked.0 <= term_cols && max_rows.is_none_or(|max_rows| asked.1 <= max_rows) {
+            return None;
+        }
+        let cells = if self.ascii.rich {
+            term_cols / 2
+        } else {
+            term_cols
+        };
+        self.ascii.width = self.ascii.width.min(cells.max(1));
+        if let Some(max_rows) = max_rows {
+            self.height = Some(self.height.map_or(max_rows, |height| height.min(max_rows)));
+        }
+        self.fit = FitMode::Contain;
+        let (width, height) = self.video_size;
+        self.configure_for(width, height, self.source_frame_rate);
+        Some(asked)
+    }
+
+    /// Rows the image has to fit in: `--height`, or else what the terminal has left above the
+    /// subtitles, the status line and the line the cursor ends up on, so it doesn't scroll.
+    /// The terminal only counts when it also sets the width, or with `FitMode::Height`.
+    fn max_rows(&self) -> Option<usize> {
+        if let Some(height) = self.height {
+            return Some(height);
+        }
+        let (_, term_rows) = self.terminal_size?;
+        let below = self.subtitle_rows() + self.hud_rows() + 1;
+        (self.auto_width || self.fit == FitMode::Height).then(|| term_rows.saturating_sub(below))
+    }
+
+    /// Columns and rows of a rendered frame.
+    pub fn output_size(&self) -> (usize, usize) {
+        let (step_x, step_y) = (self.sampling_rate.0.max(1), self.sampling_rate.1.max(1));
+        (
+            self.video_size.0.div_ceil(step_x),
+            ascii::output_rows(self.video_size.1, step_y, &self.ascii),
+        )
+    }
+
+    /// Characters in a rendered row, rich cells taking two.
+    pub fn output_columns(&self) -> usize {
+        let (cols, _) = self.output_size();
+        if self.ascii.rich {
+            cols * 2
+        } else {
+            cols
+        }
+    }
+
+    /// Rows the status line takes below the image. Single-row output redrawn with a carriage
+    /// return has nowhere to put it.
+    pub fn hud_rows(&self) -> usize {
+        (self.hud && self.redraw_mode != RedrawMode::CarriageReturn) as usize
+    }
+
+    /// Rows kept for subtitles below the image, enough for the longest cue so the image stays
+    /// put as cues come and go.
+    pub fn subtitle_rows(&self) -> usize {
+        match self.redraw_mode {
+            RedrawMode::CarriageReturn => 0,
+            _ => subtitles::max_lines(&self.subtitles),
+        }
+    }
+
+    /// Geometry and timing of the output, for tools wrapping the player.
+    pub fn metadata(&self) -> serde_json::Value {
+        let (cols, rows) = self.output_size();
+        serde_json::json!({
+            "video_size": [self.video_size.0, self.video_size.1],
+            "output_size": [cols, rows],
+            "frame_rate": self.frame_rate,
+            "aspect_ratio": self.aspect_ratio,
+            "duration": self.duration.map(|duration| duration.as_secs_f64()),
+        })
     }
 }
-#[derive(PartialEq, Debug, Copy, Clone)]
-enum ControlSignal {
-    Stop,
-    Go,
+
+fn flag_value<'a, T: std::str::FromStr>(
+    args: &mut impl Iterator<Item = &'a String>,
+    flag: &str,
+) -> Result<T, ConfigError> {
+    let value = args
+        .next()
+        .ok_or_else(|| ConfigError::MissingValue(flag.to_string()))?;
+    value.parse().map_err(|_| ConfigError::InvalidValue {
+        flag: flag.to_string(),
+        value: value.clone(),
+    })
+}
+
+/// Whether `name` is a URL of one of the network protocols played straight from ffmpeg, rather
+/// than a path. The scheme is matched case-insensitively.
+pub fn is_url(name: &str) -> bool {
+    name.split_once("://").is_some_and(|(scheme, _)| {
+        globals::URL_SCHEMES
+            .iter()
+            .any(|known| scheme.eq_ignore_ascii_case(known))
+    })
+}
+
+/// Whether `path` looks like a still image by its extension. Those are printed once instead of
+/// played.
+pub fn is_still_image(path: &str) -> bool {
+    const EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "bmp", "webp", "tiff"];
+    std::path::Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()))
+}
+
+fn positive_flag_value<'a>(
+    args: &mut impl Iterator<Item = &'a String>,
+    flag: &str,
+) -> Result<f32, ConfigError> {
+    let value: f32 = flag_value(args, flag)?;
+    if value > 0.0 && value.is_finite() {
+        Ok(value)
+    } else {
+        Err(ConfigError::InvalidValue {
+            flag: flag.to_string(),
+            value: value.to_string(),
+        })
+    }
 }
 
-//Player
-const NULL_FRAME: &str = "\0";
-struct Player {
-    queue: VecDeque<String>,
-    queue_size: usize,
-    is_playing: bool,
-    config: Config,
-    decoder: Arc<Mutex<Decoder>>,
-    tx_data: mpsc::Sender<String>,
-    rx_data: mpsc::Receiver<String>,
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run(decoder: Decoder, cfg: Config) -> Result<PlaybackSummary, PlayerError> {
+    if let Some(path) = &cfg.output {
+        let frames = export::Frames::create(path)?;
+        return run_to_writer(decoder, cfg, frames);
+    }
+    // Declared first so it's dropped last, after the keyboard has let go of the terminal.
+    let _terminal = term::TerminalGuard::new(
+        cfg.redraw_mode == RedrawMode::CarriageReturn,
+        cfg.ascii_only,
+    );
+    // The soundtrack only lines up with frames played one after another at their own pace,
+    // and would need a second read of a pipe or a second connection to a stream.
+    #[cfg(feature = "audio")]
+    let audio = match cfg.mute
+        || cfg.speed != 1.0
+        || cfg.keyframes_only
+        || cfg.step
+        || cfg.reads_stdin()
+        || cfg.reads_network()
+    {
+        true => None,
+        false => audio::Audio::start(&cfg.file_name),
+    };
+    let mut player = Player::new(cfg, decoder, Box::new(std::io::stdout()));
+    #[cfg(feature = "audio")]
+    if let Some(audio) = audio {
+        player.set_audio(audio);
+    }
+    // Space pauses, arrows seek, q quits. Restores the terminal once dropped after playback.
+    #[cfg(unix)]
+    let _keyboard = term::Keyboard::listen().map(|(keyboard, commands)| {
+        player.set_commands(commands);
+        keyboard
+    });
+    player.play()
 }
-impl Player {
-    pub fn new(cfg: Config, decoder: Decoder) -> Player {
-        let queue = VecDeque::with_capacity(globals::FRAME_BACKLOG);
-
-        let (tx_data, rx_data) = mpsc::channel();
-
-        Player {
-            queue,
-            queue_size: globals::FRAME_BACKLOG,
-            is_playing: false,
-            config: cfg,
-            decoder: Arc::new(Mutex::new(decoder)),
-            tx_data,
-            rx_data,
+
+/// Converts the first frame of `decoder` and writes it to `out` once, for still images.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn print_still(
+    mut decoder: Decoder,
+    cfg: &Config,
+    out: &mut impl std::io::Write,
+) -> Result<(), PlayerError> {
+    let frame = decoder.decode_raw().map_err(PlayerError::Decode)?;
+    out.write_all(ascii::rgb_to_ascii(frame.data(0), cfg).as_bytes())?;
+    if cfg.ascii.color != ascii::ColorMode::Mono {
+        out.write_all(ascii::RESET_COLOR.as_bytes())?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Prints a still image like `print_still`, to the `--output` path if one was given, otherwise
+/// to stdout.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run_still(decoder: Decoder, cfg: &Config) -> Result<(), PlayerError> {
+    match &cfg.output {
+        Some(path) => print_still(decoder, cfg, &mut export::Frames::create(path)?),
+        None => print_still(decoder, cfg, &mut std::io::stdout()),
+    }
+}
+
+/// Decodes and converts the first `count` frames playback would show (every frame, unless
+/// `--keyframes-only` or `--max-fps` leave some out) on the calling thread, without pacing or a
+/// terminal, so tests and bug reports get the same text every time. Frames are bare
+/// `rgb_to_ascii` output, without status line, subtitles, redraw escapes or interpolated
+/// frames in between. Fewer come back if the stream ends first.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn render_frames(
+    decoder: Decoder,
+    cfg: &Config,
+    count: usize,
+) -> Result<Vec<String>, PlayerError> {
+    let mut source = player::Source::new(decoder);
+    let mut frames = Vec::with_capacity(count);
+    let mut index = 0;
+    while frames.len() < count {
+        let frame = match source.decode_raw(cfg.keyframes_only) {
+            Err(video_rs::Error::ReadExhausted) => break,
+            frame => frame.map_err(PlayerError::Decode)?,
+        };
+        let skipped = index % cfg.frame_stride != 0;
+        index += 1;
+        if !skipped {
+            frames.push(ascii::rgb_to_ascii(frame.data(0), cfg));
         }
     }
+    Ok(frames)
+}
 
-    pub fn play(&mut self) {
-        self.is_playing = true;
+/// Plays the whole stream into `out` instead of the terminal.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run_to_writer(
+    decoder: Decoder,
+    cfg: Config,
+    out: impl std::io::Write + 'static,
+) -> Result<PlaybackSummary, PlayerError> {
+    let mut player = Player::new(cfg, decoder, Box::new(out));
+    player.play()
+}
 
-        let mut prev = Instant::now();
+/// Like `run_to_writer`, calling `on_frame` after every rendered frame.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn run_with_callback(
+    decoder: Decoder,
+    cfg: Config,
+    out: impl std::io::Write + 'static,
+    on_frame: impl FnMut(FrameStats) + 'static,
+) -> Result<PlaybackSummary, PlayerError> {
+    let mut player = Player::new(cfg, decoder, Box::new(out));
+    player.set_on_frame(on_frame);
+    player.play()
+}
 
-        let con_mut = Arc::new((Condvar::new(), Mutex::new(ControlSignal::Go)));
-        self.spawn_frame_parser(Arc::clone(&con_mut));
-        let (condvar, mtx) = &*con_mut;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let mut stream_exhausted = false;
-        let mut reached_max_capacity = false; // Flag to track when queue reaches max capacity
+    fn config(flags: &[&str]) -> Config {
+        let args: Vec<String> = ["terminal_player", "clip.mp4"]
+            .iter()
+            .chain(flags)
+            .map(|arg| arg.to_string())
+            .collect();
+        Config::from_args(&args).unwrap()
+    }
 
-        loop {
-            let capacity = self.queue_size - self.queue.len();
+    #[test]
+    fn sample_resolves_inside_the_crate() {
+        if std::env::var_os(globals::SAMPLE_ENV).is_none() {
+            let sample =
+                std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("samples/sample.mp4");
+            assert_eq!(std::path::PathBuf::from(get_sample_mp4()), sample);
+        }
+    }
 
-            let action = match capacity {
-                c if c <= 10 => ControlSignal::Stop,
-                _ if reached_max_capacity && capacity >= self.queue_size / 2 => {
-                    reached_max_capacity = false; // Reset flag when queue drops to 50%
-                    ControlSignal::Go
-                }
-                _ => ControlSignal::Go,
-            };
+    #[test]
+    fn still_images_are_told_apart_by_extension() {
+        assert!(is_still_image("logo.png"));
+        assert!(is_still_image("/tmp/Photo.JPG"));
+        assert!(!is_still_image("clip.mp4"));
+        assert!(!is_still_image("png"));
+    }
 
-            let mut signal = mtx.lock().unwrap();
-            if action == ControlSignal::Stop || *signal == ControlSignal::Stop {
-                *signal = action;
-                condvar.notify_one();
-            }
+    #[test]
+    fn urls_are_told_apart_by_scheme() {
+        assert!(is_url("http://example.com/clip.mp4"));
+        assert!(is_url("HTTPS://example.com/live.m3u8"));
+        assert!(is_url("rtsp://10.0.0.2:554/stream"));
+        assert!(!is_url("ftp://example.com/clip.mp4"));
+        assert!(!is_url("clips/http_clip.mp4"));
+        assert!(!config(&[]).reads_network());
+    }
 
-            drop(signal);
+    #[test]
+    fn configure_for_common_resolutions() {
+        let cases = [
+            // (width flag, source size, fps), (sampling rate, delta_t ms)
+            (("72", (1920, 1080), 30.0), ((27, 54), 33)),
+            (("64", (1280, 720), 60.0), ((20, 40), 16)),
+            (("80", (640, 480), 29.97), ((8, 16), 33)),
+            (("100", (480, 640), 25.0), ((5, 10), 40)),
+        ];
+        for ((width, (w, h), fps), (sampling_rate, delta_t)) in cases {
+            let mut cfg = config(&["-w", width]);
+            cfg.configure_for(w, h, fps);
+            assert_eq!(cfg.video_size, (w, h));
+            assert_eq!(cfg.sampling_rate, sampling_rate, "{}x{}", w, h);
+            assert!(cfg.output_size().0 <= width.parse().unwrap());
+            assert!((cfg.aspect_ratio - w as f32 / h as f32).abs() < f32::EPSILON);
+            assert_eq!(cfg.frame_rate, fps as u64);
+            assert_eq!(cfg.delta_t_ms.as_millis(), delta_t);
+        }
+    }
 
-            if !stream_exhausted && action != ControlSignal::Stop {
-                match self.rx_data.recv() {
-                    Ok(frame) if &frame == NULL_FRAME => {
-                        stream_exhausted = true;
-                    }
-                    Ok(frame) => {
-                        self.queue.push_front(frame);
-                    }
-                    Err(_) => {
-                        stream_exhausted = true;
-                    }
-                }
-            }
+    #[test]
+    fn configure_for_narrow_source_keeps_sampling_positive() {
+        let mut cfg = config(&["-w", "200"]);
+        cfg.configure_for(64, 32, 25.0);
+        assert_eq!(cfg.sampling_rate, (1, 2));
+    }
 
-            if self.should_skip_rendering(prev) {
-                continue;
-            }
+    #[test]
+    fn configure_for_paces_keyframes_as_slideshow() {
+        let mut cfg = config(&["--keyframes-only"]);
+        cfg.configure_for(1920, 1080, 30.0);
+        assert_eq!(cfg.delta_t_ms.as_millis(), 500);
+    }
 
-            let frame = match self.queue.pop_back() {
-                None => {
-                    if stream_exhausted {
-                        break;
-                    } else {
-                        continue; // wait for the frame
-                    }
-                }
-                Some(f) => f,
-            };
+    #[test]
+    fn configure_for_caps_extreme_frame_rates() {
+        let mut cfg = config(&[]);
+        cfg.configure_for(640, 480, 1000.0);
+        assert_eq!(cfg.source_frame_rate, 1000.0);
+        assert_eq!(cfg.frame_stride, 9);
+        assert_eq!(cfg.frame_rate, 111);
+        assert_eq!(cfg.delta_t_ms.as_millis(), 9);
 
-            self.render_frame(&frame);
-            prev = Instant::now();
+        let mut cfg = config(&["--max-fps", "30"]);
+        cfg.configure_for(640, 480, 1000.0);
+        assert_eq!(cfg.frame_stride, 34);
+        assert_eq!(cfg.delta_t_ms.as_millis(), 34);
+    }
 
-            // Set flag when queue reaches maximum capacity
-            if !reached_max_capacity && self.queue.len() == self.queue_size {
-                reached_max_capacity = true;
-            }
-        }
+    #[test]
+    fn configure_for_keeps_normal_rates_and_handles_missing_ones() {
+        let mut cfg = config(&[]);
+        cfg.configure_for(640, 480, 60.0);
+        assert_eq!(cfg.frame_stride, 1);
+        assert_eq!(cfg.frame_rate, 60);
+
+        cfg.configure_for(640, 480, 0.0);
+        assert_eq!(cfg.source_frame_rate, globals::DEF_FPS);
+        assert_eq!(cfg.delta_t_ms.as_millis(), 33);
     }
 
-    fn spawn_frame_parser(&self, condvar: Arc<(Condvar, Mutex<ControlSignal>)>) {
-        let cfg = self.config.clone();
-        let decoder = Arc::clone(&self.decoder);
-        let tx = self.tx_data.clone();
-        std::thread::spawn(move || {
-            let mut decoder: std::sync::MutexGuard<Decoder> = decoder.lock().unwrap();
-            for frame in decoder.decode_raw_iter() {
-                let frame = match frame {
-                    Err(video_rs::Error::ReadExhausted) => {
-                        println!("Stream exhausted");
-                        tx.send(String::from(NULL_FRAME)).unwrap();
-                        break; //stream exhausted, thread done
-                    }
-                    Ok(v) => v,
-                    Err(e) => {
-                        eprint!("{}", e);
-                        exit(2);
-                    }
-                };
+    #[test]
+    fn flag_values_can_follow_an_equals_sign() {
+        assert_eq!(config(&["--width=40"]).ascii.width, 40);
+        assert_eq!(config(&["-w=40"]).ascii.width, 40);
+        assert_eq!(config(&["-w", "40"]).ascii.width, 40);
+        assert_eq!(config(&["--loop=2"]).loop_count, Some(2));
+        assert_eq!(config(&["--charset= .="]).ascii.charset, [' ', '.', '=']);
+    }
 
-                let (condvar, mutex) = &*condvar;
-                let mut signal = mutex.lock().unwrap();
-                while *signal == ControlSignal::Stop {
-                    signal = condvar.wait(signal).unwrap();
-                }
-                let frame_str = ascii::rgb_to_ascii(frame.data(0), &cfg);
-                tx.send(frame_str).unwrap();
-            }
-        });
+    #[test]
+    fn gamma_and_luma_weights_are_configurable() {
+        let cfg = config(&["--gamma", "2.2", "--luma", "601"]);
+        assert_eq!(cfg.ascii.gamma, 2.2);
+        assert_eq!(cfg.ascii.luma, ascii::LumaWeights::Rec601);
+        assert_eq!(config(&[]).ascii.luma, ascii::LumaWeights::Rec709);
     }
 
-    fn render_frame(&self, chars: &str) {
-        ascii::clear_screen();
-        io::stdout().flush().expect("Failed to flush stdout");
-        print!("{}", chars);
+    #[test]
+    fn loop_takes_an_optional_count() {
+        let cfg = config(&["--loop", "3"]);
+        assert!(cfg.loop_playback);
+        assert_eq!(cfg.loop_count, Some(3));
+
+        let cfg = config(&["--loop", "--invert"]);
+        assert!(cfg.loop_playback && cfg.ascii.invert);
+        assert_eq!(cfg.loop_count, None);
     }
 
-    fn should_skip_rendering(&self, prev: Instant) -> bool {
-        let elapsed = prev.elapsed();
-        return elapsed < self.config.delta_t_ms;
+    #[test]
+    fn a_dash_or_stdin_flag_reads_the_video_from_stdin() {
+        assert!(config(&["-"]).reads_stdin());
+        assert!(config(&["--stdin", "-w", "40"]).reads_stdin());
+        assert!(!config(&[]).reads_stdin());
+
+        let args: Vec<String> = ["terminal_player", "-", "--loop"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert!(matches!(
+            Config::from_args(&args),
+            Err(ConfigError::Conflict(_))
+        ));
     }
-}
-//ASCII
-pub mod ascii {
-    use super::*;
 
-    const CHAR_MAP: &str = " ,\":;Il!i~+_-?][}{1)(|\\/tfjrxnuvczXYUJCLQ0OZmwqpdbkhao*#MW&8%B@$";
-
-    pub fn rgb_to_ascii(rgb: &[u8], cfg: &Config) -> String {
-        let mut frame_str = String::with_capacity(cfg.frame_size);
-        for row in rgb
-            .chunks(cfg.video_size.0 * 3)
-            .step_by(cfg.sampling_rate.1 * 3)
-        {
-            for pixel in row.chunks(3).step_by(cfg.sampling_rate.0) {
-                let ascii_char = rgb_to_ascii_char(pixel);
-                frame_str.push(ascii_char);
-            }
-            frame_str.push('\n');
-        }
-        frame_str
-    }
-    pub fn rgb_to_ascii_buff(rgb: &[u8], cfg: &Config, buff: &mut String) {
-        for row in rgb
-            .chunks(cfg.video_size.0 * 3)
-            .step_by(cfg.sampling_rate.1 * 3)
-        {
-            for pixel in row.chunks(3).step_by(cfg.sampling_rate.0) {
-                let ascii_char = rgb_to_ascii_char(pixel);
-                buff.push(ascii_char);
-            }
-            buff.push('\n');
+    #[test]
+    fn charset_replaces_the_ramp() {
+        let cfg = config(&["--charset", " .:-=+*#%@"]);
+        assert_eq!(cfg.ascii.charset.len(), 10);
+        assert_eq!(cfg.ascii.charset[9], '@');
+        let cfg = config(&["--charset", "⠀⠁⠃⠇"]);
+        assert_eq!(cfg.ascii.charset, ['⠀', '⠁', '⠃', '⠇']);
+    }
+
+    #[test]
+    fn ascii_only_appends_frames() {
+        let cfg = config(&["--ascii-only"]);
+        assert_eq!(cfg.redraw_mode, RedrawMode::Append);
+    }
+
+    #[test]
+    fn ascii_only_rejects_non_ascii_output() {
+        let args = |flags: &[&str]| -> Vec<String> {
+            ["terminal_player", "clip.mp4", "--ascii-only"]
+                .iter()
+                .chain(flags)
+                .map(|arg| arg.to_string())
+                .collect()
+        };
+        assert!(Config::from_args(&args(&["--rich"])).is_err());
+        assert!(Config::from_args(&args(&["--redraw-mode", "home"])).is_err());
+        assert!(Config::from_args(&args(&["--redraw-mode", "append"])).is_ok());
+
+        let mut cfg = config(&["--ascii-only"]);
+        cfg.ascii.color = ascii::ColorMode::TrueColor;
+        assert!(cfg.validate().is_err());
+        cfg.ascii.color = ascii::ColorMode::Mono;
+        cfg.ascii.charset = " .░▒▓".chars().collect();
+        assert!(cfg.validate().is_err());
+    }
+
+    #[test]
+    fn configure_for_interpolates_up_to_target_rate() {
+        let mut cfg = config(&["--interpolate", "60"]);
+        cfg.configure_for(640, 480, 15.0);
+        assert_eq!(cfg.interpolation_steps, 4);
+        assert_eq!(cfg.frame_rate, 60);
+        assert_eq!(cfg.delta_t_ms.as_millis(), 16);
+
+        // Never slower than the source.
+        cfg.configure_for(640, 480, 60.0);
+        assert_eq!(cfg.interpolation_steps, 1);
+    }
+
+    #[test]
+    fn from_args_reports_each_error() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+        let cases = [
+            (
+                args(&["tp", "clip.mp4", "--bogus"]),
+                ConfigError::UnknownFlag(String::from("--bogus")),
+            ),
+            (
+                args(&["tp", "clip.mp4", "--max-fps"]),
+                ConfigError::MissingValue(String::from("max-fps")),
+            ),
+            (
+                args(&["tp", "clip.mp4", "--fit", "stretch"]),
+                ConfigError::InvalidValue {
+                    flag: String::from("fit"),
+                    value: String::from("stretch"),
+                },
+            ),
+            (
+                args(&["tp", "clip.mp4", "--buffer", "0"]),
+                ConfigError::InvalidValue {
+                    flag: String::from("buffer"),
+                    value: String::from("0"),
+                },
+            ),
+            (
+                args(&["tp", "clip.mp4", "-w", "wide"]),
+                ConfigError::InvalidValue {
+                    flag: String::from("width"),
+                    value: String::from("wide"),
+                },
+            ),
+            (
+                args(&["tp", "clip.mp4", "--cell-aspect", "0"]),
+                ConfigError::InvalidValue {
+                    flag: String::from("cell-aspect"),
+                    value: String::from("0"),
+                },
+            ),
+            (
+                args(&["tp", "clip.mp4", "--max-mem", &usize::MAX.to_string()]),
+                ConfigError::InvalidValue {
+                    flag: String::from("max-mem"),
+                    value: usize::MAX.to_string(),
+                },
+            ),
+            (
+                args(&["tp", "clip.mp4", "-w"]),
+                ConfigError::MissingValue(String::from("width")),
+            ),
+            (
+                args(&["tp", "clip.mp4", "--width="]),
+                ConfigError::InvalidValue {
+                    flag: String::from("width"),
+                    value: String::new(),
+                },
+            ),
+            (
+                args(&["tp", "clip.mp4", "--widthfoo", "72"]),
+                ConfigError::UnknownFlag(String::from("--widthfoo")),
+            ),
+            (args(&["tp"]), ConfigError::MissingFile),
+            (
+                args(&["tp", "--edges", "--color"]),
+                ConfigError::Conflict(String::from("--edges can't be combined with color output.")),
+            ),
+            (
+                args(&["tp", "--interpolate", "60", "--palette", "c64"]),
+                ConfigError::Conflict(String::from(
+                    "--interpolate can't be combined with color output.",
+                )),
+            ),
+            (
+                args(&["tp", "--interpolate", "60", "--rich"]),
+                ConfigError::Conflict(String::from("--interpolate can't be combined with --rich.")),
+            ),
+            (
+                args(&["tp", "--halfblock", "--braille"]),
+                ConfigError::Conflict(String::from(
+                    "--halfblock can't be combined with --braille.",
+                )),
+            ),
+            (
+                args(&["tp", "rtsp://cam.local/live", "--loop"]),
+                ConfigError::Conflict(String::from(
+                    "--loop can't be combined with a URL, a live stream can't be rewound.",
+                )),
+            ),
+            (
+                args(&["tp", "-o", "out.txt", "--redraw-mode", "clear"]),
+                ConfigError::Conflict(String::from(
+                    "--output can't be combined with a --redraw-mode other than append.",
+                )),
+            ),
+            (
+                args(&["tp", "clip.mp4", "--braille", "--rich"]),
+                ConfigError::Conflict(String::from("--braille can't be combined with --rich.")),
+            ),
+            (
+                args(&["tp", "clip.mp4", "--ascii-only", "--rich"]),
+                ConfigError::Conflict(String::from("--ascii-only can't be combined with --rich.")),
+            ),
+        ];
+        for (args, expected) in cases {
+            assert_eq!(
+                Config::from_args(&args).unwrap_err(),
+                expected,
+                "{:?}",
+                args
+            );
         }
     }
-    fn rgb_to_ascii_char(pixel: &[u8]) -> char {
-        let y = 0.21 * pixel[0] as f32 + 0.72 * pixel[1] as f32 + 0.07 * pixel[2] as f32;
-        let lum = y * 0.001307 * CHAR_MAP.len() as f32;
-        let index = lum as usize;
-        CHAR_MAP.chars().nth(index).unwrap_or(' ')
+
+    #[test]
+    fn frame_size_matches_rendered_frame() {
+        let mut cfg = config(&[]);
+        cfg.configure_for(640, 480, 30.0);
+        let rgb: Vec<u8> = (0..640 * 480 * 3).map(|i| (i % 251) as u8).collect();
+        let frame = ascii::rgb_to_ascii(&rgb, &cfg);
+        assert!(cfg.frame_size >= frame.len());
+        assert!(cfg.frame_size as f32 <= frame.len() as f32 * 1.05);
+        assert_eq!(frame.capacity(), cfg.frame_size);
     }
 
-    pub fn clear_screen() {
-        print!("\x1B[2J\x1B[1;1H"); // Clear screen and move cursor to top-left corner
-        std::io::stdout().flush().expect("Failed to flush stdout");
+    #[test]
+    fn fit_to_terminal_uses_the_full_width() {
+        let mut cfg = config(&[]);
+        cfg.fit_to_terminal(Some((180, 60)));
+        assert_eq!(cfg.ascii.width, 180);
+        assert_eq!(cfg.terminal_size, Some((180, 60)));
+        cfg.configure_for(1920, 1080, 30.0);
+        assert_eq!(cfg.output_size().0, 175);
+
+        cfg.fit_to_terminal(Some((5000, 50)));
+        assert_eq!(cfg.ascii.width, globals::MAX_AUTO_WIDTH);
+
+        let mut cfg = config(&["--rich"]);
+        cfg.fit_to_terminal(Some((180, 50)));
+        assert_eq!(cfg.ascii.width, 90);
     }
-}
 
-pub fn run(decoder: Decoder, cfg: Config) {
-    let mut player: Player = Player::new(cfg, decoder);
-    player.play();
+    #[test]
+    fn fit_to_terminal_leaves_room_for_the_status_line() {
+        let mut cfg = config(&[]);
+        cfg.fit_to_terminal(Some((180, 50)));
+        cfg.configure_for(1920, 1080, 30.0);
+        // One sample step wider, the most that fits.
+        assert_eq!(cfg.sampling_rate, (12, 24));
+        assert_eq!(cfg.output_size(), (160, 45));
+
+        let mut cfg = config(&["--no-hud"]);
+        cfg.fit_to_terminal(Some((180, 51)));
+        cfg.configure_for(1920, 1080, 30.0);
+        assert_eq!(cfg.output_size(), (175, 50));
+
+        let mut cfg = config(&["-w", "180"]);
+        cfg.fit_to_terminal(Some((180, 20)));
+        cfg.configure_for(1920, 1080, 30.0);
+        assert_eq!(cfg.output_size().0, 175);
+    }
+
+    #[test]
+    fn fit_modes_bound_the_output_by_width_height_or_both() {
+        // Portrait, sized by its height only.
+        let mut cfg = config(&["--height", "30", "--fit", "height"]);
+        cfg.configure_for(480, 960, 30.0);
+        assert_eq!(cfg.output_size(), (30, 30));
+
+        // Letterboxed within both, the height binds.
+        let mut cfg = config(&["-w", "100", "--height", "30"]);
+        cfg.configure_for(640, 480, 30.0);
+        assert_eq!(cfg.fit, FitMode::Contain);
+        assert_eq!(cfg.output_size(), (80, 30));
+
+        let mut cfg = config(&["-w", "100", "--height", "30", "--fit", "width"]);
+        cfg.configure_for(640, 480, 30.0);
+        assert_eq!(cfg.output_size(), (92, 35));
+    }
+
+    #[test]
+    fn shrink_to_terminal_fits_an_explicit_size() {
+        let mut cfg = config(&["-w", "300", "--no-hud"]);
+        cfg.fit_to_terminal(Some((100, 80)));
+        cfg.configure_for(640, 480, 30.0);
+        assert_eq!(cfg.output_size(), (214, 80));
+        assert_eq!(cfg.shrink_to_terminal(), Some((214, 80)));
+        assert_eq!(cfg.output_size(), (92, 35));
+        assert_eq!(cfg.frame_rate, 30);
+        assert_eq!(cfg.shrink_to_terminal(), None);
+
+        // Appended frames may run as long as they like.
+        let mut cfg = config(&["-w", "80", "--no-clear"]);
+        cfg.fit_to_terminal(Some((100, 10)));
+        cfg.configure_for(640, 480, 30.0);
+        assert_eq!(cfg.shrink_to_terminal(), None);
+        assert_eq!(cfg.output_size(), (80, 30));
+    }
+
+    #[test]
+    fn buffer_sets_the_frames_decoded_ahead() {
+        assert_eq!(config(&[]).buffer_frames, globals::FRAME_BACKLOG);
+        assert_eq!(config(&["--buffer", "60"]).buffer_frames, 60);
+    }
+
+    #[test]
+    fn halfblock_samples_twice_as_many_rows() {
+        let mut cfg = config(&["-w", "80", "--halfblock"]);
+        assert_eq!(cfg.ascii.color, ascii::ColorMode::TrueColor);
+        let colors = config(&["--color256", "--halfblock"]).ascii.color;
+        assert_eq!(colors, ascii::ColorMode::Ansi256);
+        cfg.configure_for(640, 480, 30.0);
+        assert_eq!(cfg.sampling_rate, (8, 8));
+        assert_eq!(cfg.output_size(), (80, 30));
+    }
+
+    #[test]
+    fn output_writes_frames_only_as_fast_as_they_come() {
+        let cfg = config(&["--output", "frames.txt"]);
+        assert_eq!(cfg.output, Some(std::path::PathBuf::from("frames.txt")));
+        assert_eq!(cfg.redraw_mode, RedrawMode::Append);
+        assert!(!cfg.hud);
+        assert!(!cfg.realtime);
+        assert!(config(&[]).realtime);
+    }
+
+    #[test]
+    fn fit_to_terminal_leaves_room_for_subtitles() {
+        let mut cfg = config(&["--no-hud"]);
+        cfg.subtitles = subtitles::parse_srt("1\n00:00:01,000 --> 00:00:02,000\nTwo\nlines\n");
+        cfg.fit_to_terminal(Some((180, 50)));
+        cfg.configure_for(1920, 1080, 30.0);
+        assert_eq!(cfg.subtitle_rows(), 2);
+        assert_eq!(cfg.output_size(), (160, 45));
+    }
+
+    #[test]
+    fn fit_to_terminal_keeps_explicit_or_default_width() {
+        let mut cfg = config(&["-w", "40"]);
+        cfg.fit_to_terminal(Some((180, 50)));
+        assert_eq!(cfg.ascii.width, 40);
+
+        let mut cfg = config(&[]);
+        cfg.fit_to_terminal(None);
+        assert_eq!(cfg.ascii.width, globals::DEF_WIDTH);
+    }
+
+    #[test]
+    fn configure_for_scales_pacing_by_speed() {
+        let mut cfg = config(&["--speed", "2"]);
+        cfg.configure_for(640, 480, 30.0);
+        assert_eq!(cfg.frame_rate, 60);
+        assert_eq!(cfg.delta_t_ms.as_micros(), 16_666);
+
+        let mut cfg = config(&["-s", "0.5"]);
+        cfg.configure_for(640, 480, 30.0);
+        assert_eq!(cfg.frame_rate, 15);
+        assert_eq!(cfg.delta_t_ms.as_millis(), 66);
+
+        // Sub-millisecond pacing doesn't collapse to zero.
+        let mut cfg = config(&["--speed", "100", "--max-fps", "0"]);
+        cfg.configure_for(640, 480, 30.0);
+        assert_eq!(cfg.delta_t_ms.as_micros(), 333);
+        // And the frame rate cap still holds at high speeds.
+        let mut cfg = config(&["--speed", "100"]);
+        cfg.configure_for(640, 480, 30.0);
+        assert_eq!(cfg.frame_stride, 25);
+    }
+
+    #[test]
+    fn speed_must_be_positive() {
+        for speed in ["0", "-1", "inf"] {
+            let args: Vec<String> = ["tp", "clip.mp4", "--speed", speed]
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect();
+            assert!(
+                matches!(
+                    Config::from_args(&args),
+                    Err(ConfigError::InvalidValue { .. })
+                ),
+                "{}",
+                speed
+            );
+        }
+    }
 }
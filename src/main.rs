@@ -1,30 +1,117 @@
+use std::collections::HashMap;
+use std::io::IsTerminal;
 use std::path::Path;
-use terminal_player::Config;
-use video_rs::{Decoder, Location};
+use terminal_player::{Config, PlayerError, RedrawMode};
+use video_rs::{Decoder, DecoderBuilder, Location, Options, Url};
 
 fn main() {
+    if let Err(e) = play() {
+        eprintln!("{}", e);
+        std::process::exit(match e {
+            PlayerError::Config(_)
+            | PlayerError::Io(_)
+            | PlayerError::NotFound(_)
+            | PlayerError::Open(..)
+            | PlayerError::Unseekable
+            | PlayerError::Connection(_) => 1,
+            PlayerError::Decode(_) => 2,
+        });
+    }
+}
+
+fn play() -> Result<(), PlayerError> {
     let args: Vec<String> = std::env::args().collect();
-    let mut config = Config::from_args(&args).unwrap_or_else(|e| {
-        println!("Cannot create config: {}", e);
-        std::process::exit(1);
-    });
+    let mut config = Config::from_args(&args)?;
+    let still = terminal_player::is_still_image(&config.file_name);
+    if still {
+        // Printed once on its own, so there's no status line or subtitles to make room for.
+        config.hud = false;
+        config.subtitles.clear();
+    }
+
+    config.detect_terminal_size();
+
+    if config.output.is_none() && !std::io::stdout().is_terminal() {
+        if config.redraw_mode != RedrawMode::Append {
+            eprintln!("stdout is not a terminal, appending frames without clearing (--no-clear).");
+            config.redraw_mode = RedrawMode::Append;
+        }
+        // Frames only, for whatever reads them.
+        config.hud = false;
+    }
+
+    let local = !config.reads_stdin() && !config.reads_network();
+    if local && !Path::new(&config.file_name).is_file() {
+        return Err(PlayerError::NotFound(config.file_name));
+    }
 
     init_ffmpeg();
 
-    let decoder = create_decoder(&config.file_name).unwrap_or_else(|e| {
-        println!("Cannot create decoder from {}.\n{}", config.file_name, e);
-        std::process::exit(1);
-    });
+    let decoder = match create_decoder(&config.file_name) {
+        Ok(decoder) => decoder,
+        Err(e) if config.reads_network() => return Err(PlayerError::Connection(e)),
+        Err(e) => return Err(PlayerError::Open(config.file_name, e)),
+    };
     config.add_decoder_info(&decoder);
+    if config.output.is_none() && std::io::stdout().is_terminal() {
+        if let Some((cols, rows)) = config.shrink_to_terminal() {
+            let (fit_cols, fit_rows) = (config.output_columns(), config.output_size().1);
+            eprintln!(
+                "{}x{} characters don't fit the terminal, shrinking to {}x{}.",
+                cols, rows, fit_cols, fit_rows
+            );
+        }
+    }
+    if config.emit_meta {
+        // stderr, so stdout stays nothing but the frame stream.
+        eprintln!("{}", config.metadata());
+    }
+
+    if still {
+        return terminal_player::run_still(decoder, &config);
+    }
 
-    terminal_player::run(decoder, config);
+    let stats = config.stats;
+    let summary = terminal_player::run(decoder, config)?;
+    if stats {
+        // The terminal is restored by now, so this stays on screen.
+        eprintln!("{}", summary);
+    }
+    Ok(())
 }
 
 pub fn init_ffmpeg() {
     video_rs::init().unwrap();
 }
+/// Opens `file_name`, stdin for `-` through ffmpeg's pipe protocol, or a network stream for a
+/// URL, given up on after `NETWORK_TIMEOUT` without data.
 pub fn create_decoder(file_name: &str) -> Result<Decoder, video_rs::Error> {
-    let path = Path::new(file_name);
-    let source = Location::File(path.to_path_buf());
+    // One that doesn't parse is left to fail opening as a file.
+    if let Some(url) = Url::parse(file_name)
+        .ok()
+        .filter(|_| terminal_player::is_url(file_name))
+    {
+        let options = network_options(&url);
+        return DecoderBuilder::new(url).with_options(&options).build();
+    }
+    let source = match file_name {
+        terminal_player::STDIN => Location::Network(Url::parse("pipe:0").unwrap()),
+        _ => Location::File(Path::new(file_name).to_path_buf()),
+    };
     Decoder::new(source)
 }
+
+/// Timeouts for `url`, ffmpeg would otherwise wait on a silent host forever.
+fn network_options(url: &Url) -> Options {
+    let micros = terminal_player::NETWORK_TIMEOUT.as_micros().to_string();
+    let mut options = HashMap::new();
+    // `rw_timeout` bounds every read whatever the protocol, `timeout` connecting over HTTP.
+    // RTSP's `timeout` means listening for a connection in older ffmpeg, so it's left out, and
+    // the data comes over TCP, which firewalls let through where UDP gets dropped.
+    options.insert(String::from("rw_timeout"), micros.clone());
+    match url.scheme() {
+        "rtsp" => options.insert(String::from("rtsp_transport"), String::from("tcp")),
+        _ => options.insert(String::from("timeout"), micros),
+    };
+    Options::from(options)
+}
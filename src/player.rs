@@ -0,0 +1,1334 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt::{self, Write as _},
+    io::{self, Write},
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Condvar, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+use video_rs::{decode::DecoderSplit, frame::RawFrame, Decoder, Reader};
+
+use crate::{ascii, globals, Config, ConfigError, RedrawMode};
+
+/// A decoder split from the reader feeding it, so packets can be passed over before they're
+/// decoded at all.
+pub(crate) struct Source {
+    decoder: DecoderSplit,
+    reader: Reader,
+    stream: usize,
+}
+
+impl Source {
+    pub(crate) fn new(decoder: Decoder) -> Source {
+        let (decoder, reader, stream) = decoder.into_parts();
+        Source {
+            decoder,
+            reader,
+            stream,
+        }
+    }
+
+    /// The next frame, or with `keyframes_only` the next keyframe. Keyframes decode on their
+    /// own, so the packets in between are never sent to the codec.
+    pub(crate) fn decode_raw(&mut self, keyframes_only: bool) -> Result<RawFrame, video_rs::Error> {
+        loop {
+            let packet = self.reader.read(self.stream)?;
+            if keyframes_only && !packet.is_key() {
+                continue;
+            }
+            if let Some(frame) = self.decoder.decode_raw(packet)? {
+                return Ok(frame);
+            }
+        }
+    }
+
+    fn seek(&mut self, timestamp_ms: i64) -> Result<(), video_rs::Error> {
+        self.reader.seek(timestamp_ms)
+    }
+
+    fn time_base(&self) -> video_rs::ffmpeg::Rational {
+        self.decoder.time_base()
+    }
+}
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+enum ControlSignal {
+    Stop,
+    Go,
+}
+
+/// Keeps the parser from starting and stopping on every frame when the queue hovers around a
+/// single threshold. The parser is stopped once the queue holds `high_watermark` frames and
+/// allowed to resume only after playback has drained it down to `low_watermark`.
+struct FlowControl {
+    /// Half the queue: enough cushion that a resumed parser has time to catch up.
+    low_watermark: usize,
+    /// A tenth of the queue short of full, at most `HIGH_WATERMARK_SLACK`, so in-flight frames
+    /// still fit.
+    high_watermark: usize,
+    signal: ControlSignal,
+}
+
+const HIGH_WATERMARK_SLACK: usize = 10;
+
+impl FlowControl {
+    fn new(queue_size: usize) -> FlowControl {
+        FlowControl {
+            low_watermark: queue_size / 2,
+            high_watermark: queue_size
+                .saturating_sub((queue_size / 10).clamp(1, HIGH_WATERMARK_SLACK)),
+            signal: ControlSignal::Go,
+        }
+    }
+
+    fn update(&mut self, queue_len: usize) -> ControlSignal {
+        self.signal = match self.signal {
+            ControlSignal::Go if queue_len >= self.high_watermark => ControlSignal::Stop,
+            ControlSignal::Stop if queue_len <= self.low_watermark => ControlSignal::Go,
+            signal => signal,
+        };
+        self.signal
+    }
+}
+
+/// Frames waiting to be rendered with their stream timestamps and when they were decoded,
+/// newest at the front. Tracks the bytes it holds so a memory cap can be enforced no matter how
+/// large individual frames are.
+struct FrameQueue {
+    frames: VecDeque<(String, Duration, Instant)>,
+    bytes: usize,
+    max_bytes: Option<usize>,
+    dropped: usize,
+    capped: bool,
+}
+
+impl FrameQueue {
+    fn new(capacity: usize, max_bytes: Option<usize>) -> FrameQueue {
+        FrameQueue {
+            frames: VecDeque::with_capacity(capacity),
+            bytes: 0,
+            max_bytes,
+            dropped: 0,
+            capped: false,
+        }
+    }
+
+    /// Queues `frame`, dropping the oldest frames if that goes over the memory cap.
+    fn push(&mut self, frame: String, timestamp: Duration, decoded: Instant) {
+        self.bytes += frame.len();
+        self.frames.push_front((frame, timestamp, decoded));
+        let Some(max_bytes) = self.max_bytes else {
+            return;
+        };
+        if self.bytes <= max_bytes {
+            self.capped = false;
+            return;
+        }
+        if !self.capped {
+            eprintln!(
+                "Frame queue reached the {} byte memory cap, dropping the oldest frames.",
+                max_bytes
+            );
+            self.capped = true;
+        }
+        while self.bytes > max_bytes && self.frames.len() > 1 {
+            self.pop();
+            self.dropped += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<(String, Duration, Instant)> {
+        let frame = self.frames.pop_back()?;
+        self.bytes -= frame.0.len();
+        Some(frame)
+    }
+
+    fn clear(&mut self) {
+        self.frames.clear();
+        self.bytes = 0;
+    }
+
+    /// Fill level in frame slots out of `slots`, going by memory use when that's the tighter
+    /// limit, so flow control stops the parser before the cap starts dropping frames.
+    fn fill(&self, slots: usize) -> usize {
+        match self.max_bytes {
+            Some(max_bytes) => self.frames.len().max(self.bytes * slots / max_bytes.max(1)),
+            None => self.frames.len(),
+        }
+    }
+}
+
+/// Playback controls, usually coming from the keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Freeze on the current frame, or carry on if already frozen.
+    TogglePause,
+    /// Jump by this many milliseconds, backwards when negative.
+    Seek(i64),
+    /// Pause, if playing, and show the next frame.
+    Step,
+    /// Pause, if playing, and show the frame before the current one.
+    StepBack,
+    Quit,
+}
+
+/// How often a player checks for commands while it waits, paused or for the next frame to be
+/// due.
+const PAUSE_POLL: Duration = Duration::from_millis(20);
+
+/// Handed to the `on_frame` callback after every rendered frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Position among rendered frames, starting at 0.
+    pub index: usize,
+    /// Position of the frame in the stream.
+    pub timestamp: Duration,
+    /// Time spent writing the frame out.
+    pub render_time: Duration,
+    /// From the frame being decoded to it being written out.
+    pub latency: Duration,
+    /// Frames still waiting in the queue.
+    pub queue_depth: usize,
+    pub dropped_so_far: usize,
+}
+
+/// What a finished playback amounted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlaybackSummary {
+    pub frames_rendered: usize,
+    /// Frames thrown away without being rendered, by the memory cap or to keep up when
+    /// rendering falls behind.
+    pub frames_dropped: usize,
+    /// Wall-clock time from the start of playback to the last frame.
+    pub duration: Duration,
+    /// Mean time from a frame being decoded to it being written out.
+    pub average_latency: Duration,
+}
+
+impl PlaybackSummary {
+    /// Frames rendered per second of playback.
+    pub fn fps(&self) -> f64 {
+        match self.duration.is_zero() {
+            true => 0.0,
+            false => self.frames_rendered as f64 / self.duration.as_secs_f64(),
+        }
+    }
+}
+
+impl fmt::Display for PlaybackSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} frames rendered, {} dropped in {:.2}s: {:.1} fps, {:.1} ms from decoding to screen.",
+            self.frames_rendered,
+            self.frames_dropped,
+            self.duration.as_secs_f64(),
+            self.fps(),
+            self.average_latency.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+/// Why playback stopped before the end of the stream, or never started.
+#[derive(Debug)]
+pub enum PlayerError {
+    Config(ConfigError),
+    Decode(video_rs::Error),
+    Io(io::Error),
+    /// There's no file at the path given.
+    NotFound(String),
+    /// The file is there, but ffmpeg can't make out a video stream in it.
+    Open(String, video_rs::Error),
+    /// A seek was asked of input that can only be read front to back, like stdin.
+    Unseekable,
+    /// A network stream couldn't be reached, broke off, or went silent for longer than
+    /// `NETWORK_TIMEOUT`.
+    Connection(video_rs::Error),
+}
+
+impl fmt::Display for PlayerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlayerError::Config(e) => write!(f, "Cannot create config: {}", e),
+            PlayerError::Decode(e) => write!(f, "Failed to decode frame: {}", e),
+            PlayerError::Io(e) => write!(f, "Failed to write frame: {}", e),
+            PlayerError::NotFound(path) => {
+                write!(f, "Cannot find {}.", path)?;
+                if *path == globals::get_sample_mp4() {
+                    write!(
+                        f,
+                        "\nPass a video to play, or point {} at one.",
+                        globals::SAMPLE_ENV
+                    )?;
+                }
+                Ok(())
+            }
+            // Pipes can't be rewound, which formats like mp4 with its index at the end need.
+            PlayerError::Open(path, e) if path == globals::STDIN => write!(
+                f,
+                "Cannot create decoder from stdin, the format may need seeking a pipe can't do \
+                 (try a fragmented mp4, mkv, webm or mpegts).\n{}",
+                e
+            ),
+            PlayerError::Open(path, e) => write!(f, "Cannot create decoder from {}.\n{}", path, e),
+            PlayerError::Unseekable => write!(f, "Cannot seek in a stream read from stdin."),
+            PlayerError::Connection(e) => write!(
+                f,
+                "Lost the connection to the stream, the host may be unreachable or didn't \
+                 answer within {} seconds.\n{}",
+                globals::NETWORK_TIMEOUT.as_secs(),
+                e
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PlayerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PlayerError::Config(e) => Some(e),
+            PlayerError::Decode(e) => Some(e),
+            PlayerError::Io(e) => Some(e),
+            PlayerError::NotFound(_) => None,
+            PlayerError::Open(_, e) => Some(e),
+            PlayerError::Unseekable => None,
+            PlayerError::Connection(e) => Some(e),
+        }
+    }
+}
+
+impl From<ConfigError> for PlayerError {
+    fn from(e: ConfigError) -> Self {
+        PlayerError::Config(e)
+    }
+}
+
+impl From<io::Error> for PlayerError {
+    fn from(e: io::Error) -> Self {
+        PlayerError::Io(e)
+    }
+}
+
+/// Sent from the parser thread to the player. Frames and the end of the stream are tagged with
+/// the seek epoch they were decoded in, so anything from before a seek can be told apart.
+enum Message {
+    Frame {
+        text: String,
+        timestamp: Duration,
+        decoded: Instant,
+        epoch: usize,
+    },
+    Exhausted(usize),
+    Failed(video_rs::Error),
+}
+
+/// State the parser thread waits on, all behind one lock so any change can wake it up.
+struct ParserState {
+    signal: ControlSignal,
+    /// Bumped by every seek.
+    epoch: usize,
+    /// Playback is over, the parser should quit.
+    finished: bool,
+}
+
+type Control = Arc<(Condvar, Mutex<ParserState>)>;
+
+//Player
+pub struct Player {
+    queue: FrameQueue,
+    queue_size: usize,
+    is_playing: bool,
+    config: Config,
+    decoder: Arc<Mutex<Source>>,
+    control: Control,
+    /// Seek epoch of the frames worth rendering.
+    epoch: usize,
+    /// Stream position of the last rendered frame, or of the last seek.
+    position: Duration,
+    /// When the next frame should be on screen, `None` until playback (re)starts with the next
+    /// frame.
+    due: Option<Instant>,
+    /// Frames before this are thrown away unseen, to step back to an exact frame.
+    skip_until: Option<Duration>,
+    /// Decoding to rendering time summed over the frames rendered by `play`.
+    latency_total: Duration,
+    out: Box<dyn Write>,
+    /// Whether the first frame cleared the screen yet, with `RedrawMode::Home`.
+    screen_cleared: bool,
+    /// Reused to assemble frames redrawn in place.
+    redraw_buf: String,
+    on_frame: Option<Box<dyn FnMut(FrameStats)>>,
+    commands: Option<mpsc::Receiver<Command>>,
+    /// Soundtrack frames are paced by, instead of the wall clock.
+    #[cfg(feature = "audio")]
+    audio: Option<crate::audio::Audio>,
+}
+impl Player {
+    pub fn new(cfg: Config, decoder: Decoder, out: Box<dyn Write>) -> Player {
+        let queue = FrameQueue::new(cfg.buffer_frames, cfg.max_mem_bytes);
+        let state = ParserState {
+            signal: ControlSignal::Go,
+            epoch: 0,
+            finished: false,
+        };
+
+        Player {
+            queue,
+            queue_size: cfg.buffer_frames,
+            is_playing: false,
+            config: cfg,
+            decoder: Arc::new(Mutex::new(Source::new(decoder))),
+            control: Arc::new((Condvar::new(), Mutex::new(state))),
+            epoch: 0,
+            position: Duration::ZERO,
+            due: None,
+            skip_until: None,
+            latency_total: Duration::ZERO,
+            out,
+            screen_cleared: false,
+            redraw_buf: String::new(),
+            on_frame: None,
+            commands: None,
+            #[cfg(feature = "audio")]
+            audio: None,
+        }
+    }
+
+    /// Registers a callback invoked after each rendered frame.
+    pub fn set_on_frame(&mut self, on_frame: impl FnMut(FrameStats) + 'static) {
+        self.on_frame = Some(Box::new(on_frame));
+    }
+
+    /// Takes playback controls from `commands`, checked between frames without blocking.
+    pub fn set_commands(&mut self, commands: mpsc::Receiver<Command>) {
+        self.commands = Some(commands);
+    }
+
+    /// Plays `audio` along, following its position rather than the wall clock.
+    #[cfg(feature = "audio")]
+    pub(crate) fn set_audio(&mut self, audio: crate::audio::Audio) {
+        self.audio = Some(audio);
+    }
+
+    /// Moves playback to `target`. Queued frames and ones still on their way from the parser
+    /// are thrown away, playback carries on from the first frame decoded after the seek.
+    pub fn seek(&mut self, target: Duration) -> Result<(), PlayerError> {
+        // A pipe moves on with every read, ffmpeg would only fail somewhere mid-stream.
+        if self.config.reads_stdin() {
+            return Err(PlayerError::Unseekable);
+        }
+        // Taking the decoder lock waits out the frame being decoded, so every frame is decoded
+        // either before the seek and the epoch bump or after both.
+        let mut decoder = self.decoder.lock().unwrap();
+        decoder
+            .seek(target.as_millis() as i64)
+            .map_err(PlayerError::Decode)?;
+        let (condvar, mtx) = &*self.control;
+        let mut state = mtx.lock().unwrap();
+        state.epoch += 1;
+        state.signal = ControlSignal::Go;
+        condvar.notify_one();
+        self.epoch = state.epoch;
+        self.queue.clear();
+        self.position = target;
+        self.due = None;
+        self.skip_until = None;
+        #[cfg(feature = "audio")]
+        if let Some(audio) = &self.audio {
+            audio.seek(target);
+        }
+        Ok(())
+    }
+
+    /// Seeks for a key press, returning whether playback moved. Keys can't help what they're
+    /// pressed on, so input that can't seek leaves them doing nothing rather than ending
+    /// playback: stdin never can, live streams often can't.
+    fn seek_by_key(&mut self, target: Duration) -> Result<bool, PlayerError> {
+        match self.seek(target) {
+            Ok(()) => Ok(true),
+            Err(PlayerError::Unseekable) => Ok(false),
+            Err(PlayerError::Decode(_)) if self.config.reads_network() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn play(&mut self) -> Result<PlaybackSummary, PlayerError> {
+        self.is_playing = true;
+        self.latency_total = Duration::ZERO;
+        let started = Instant::now();
+
+        // The parser owns the only sender, so if it dies `recv` errors out instead of blocking.
+        let (tx_data, rx_data) = mpsc::channel();
+        // Rendered frames go back to the parser to be refilled, instead of a fresh allocation
+        // for every frame.
+        let (tx_spare, rx_spare) = mpsc::channel();
+        let parser = self.spawn_frame_parser(tx_data, rx_spare);
+
+        let result = self.play_frames(rx_data, tx_spare);
+        // Joining the parser can take a while, and isn't part of playback.
+        let duration = started.elapsed();
+        // Lines reset their own color, but a frame cut short by an error may not have.
+        let reset = match self.config.ascii.color {
+            ascii::ColorMode::Mono => Ok(()),
+            _ => self.reset_color(),
+        };
+
+        // A parser paused by flow control or at the end of the stream has to be woken up to
+        // notice it's done.
+        let (condvar, mtx) = &*self.control;
+        mtx.lock().unwrap().finished = true;
+        condvar.notify_one();
+        // Nothing times out a read from a stalled pipe, so a parser stuck in one would hold up
+        // quitting for good. It's left to end with the process instead.
+        if self.config.reads_stdin() && !parser.is_finished() {
+            drop(parser);
+        } else if let Err(panic) = parser.join() {
+            std::panic::resume_unwind(panic);
+        }
+        self.is_playing = false;
+
+        let frames_rendered = result?;
+        reset?;
+        Ok(PlaybackSummary {
+            frames_rendered,
+            frames_dropped: self.queue.dropped,
+            duration,
+            average_latency: self.latency_total / frames_rendered.max(1) as u32,
+        })
+    }
+
+    /// Renders frames until the stream runs out, returning how many were rendered.
+    fn play_frames(
+        &mut self,
+        rx_data: mpsc::Receiver<Message>,
+        tx_spare: mpsc::Sender<String>,
+    ) -> Result<usize, PlayerError> {
+        let mut stream_exhausted = false;
+        let mut flow = FlowControl::new(self.queue_size);
+        let mut rendered = 0;
+        // Stepping takes commands, without them playback would never get anywhere.
+        let mut paused = self.config.step && self.commands.is_some();
+        // Frames to show while paused, one per step.
+        let mut steps = 0;
+        let mut passes = 0;
+        let mut rendered_before_pass = 0;
+
+        loop {
+            let commands: Vec<Command> = match &self.commands {
+                Some(commands) => commands.try_iter().collect(),
+                None => Vec::new(),
+            };
+            for command in commands {
+                match command {
+                    Command::TogglePause => {
+                        paused = !paused;
+                        self.due = None;
+                        #[cfg(feature = "audio")]
+                        if let Some(audio) = &self.audio {
+                            audio.set_paused(paused);
+                        }
+                    }
+                    Command::Seek(offset_ms) => {
+                        let target = self.position.as_millis() as i64 + offset_ms;
+                        if self.seek_by_key(Duration::from_millis(target.max(0) as u64))? {
+                            stream_exhausted = false;
+                        }
+                    }
+                    Command::Step | Command::StepBack => {
+                        if command == Command::StepBack {
+                            let interval = self.frame_interval();
+                            let target = self.position.saturating_sub(interval);
+                            if self.seek_by_key(target)? {
+                                // Seeking lands on a keyframe, anything before the frame wanted
+                                // is skipped. Timestamps are rounded, hence the half frame.
+                                self.skip_until = Some(target.saturating_sub(interval / 2));
+                                stream_exhausted = false;
+                            }
+                        }
+                        paused = true;
+                        steps += 1;
+                    }
+                    Command::Quit => return Ok(rendered),
+                }
+            }
+
+            let action = flow.update(self.queue.fill(self.queue_size));
+
+            let (condvar, mtx) = &*self.control;
+            let mut state = mtx.lock().unwrap();
+            if action == ControlSignal::Stop || state.signal == ControlSignal::Stop {
+                state.signal = action;
+                condvar.notify_one();
+            }
+
+            drop(state);
+
+            if !stream_exhausted && action != ControlSignal::Stop {
+                // A paused player can't wait on a stalled parser, or it wouldn't hear commands.
+                let received = match paused && steps == 0 {
+                    true => rx_data.recv_timeout(PAUSE_POLL),
+                    false => rx_data.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                let mut message = match received {
+                    Ok(message) => Some(message),
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => None,
+                };
+                loop {
+                    match message {
+                        Some(Message::Frame {
+                            text,
+                            timestamp,
+                            decoded,
+                            epoch,
+                        }) => {
+                            if epoch == self.epoch {
+                                self.queue.push(text, timestamp, decoded);
+                            }
+                        }
+                        Some(Message::Exhausted(epoch)) => {
+                            stream_exhausted = epoch == self.epoch;
+                        }
+                        // Streams fail by the network far more often than by their data.
+                        Some(Message::Failed(e)) if self.config.reads_network() => {
+                            return Err(PlayerError::Connection(e));
+                        }
+                        Some(Message::Failed(e)) => return Err(PlayerError::Decode(e)),
+                        None => {
+                            stream_exhausted = true;
+                        }
+                    }
+                    // When behind, take whatever else has arrived, so there are newer frames to
+                    // skip ahead to.
+                    if stream_exhausted || !self.falling_behind() {
+                        break;
+                    }
+                    match rx_data.try_recv() {
+                        Ok(next) => message = Some(next),
+                        Err(_) => break,
+                    }
+                }
+            }
+
+            if paused && steps == 0 {
+                // Keep buffering until flow control stops the parser, then idle.
+                if action == ControlSignal::Stop || stream_exhausted {
+                    std::thread::sleep(PAUSE_POLL);
+                }
+                continue;
+            }
+
+            if steps == 0 && self.should_skip_rendering() {
+                self.wait_until_due();
+                continue;
+            }
+
+            let (frame, timestamp, decoded) = match self.queue.pop() {
+                None => {
+                    // Waiting on the parser isn't falling behind, there's nothing to skip.
+                    self.due = None;
+                    if !stream_exhausted {
+                        continue; // wait for the frame
+                    }
+                    if paused {
+                        // Stepped past the end, stay on the last frame.
+                        steps = 0;
+                        continue;
+                    }
+                    // Only start over once every frame of this pass is out.
+                    passes += 1;
+                    let empty_pass = rendered == rendered_before_pass;
+                    if self.loops_left(passes) && !empty_pass {
+                        rendered_before_pass = rendered;
+                        self.seek(Duration::ZERO)?;
+                        stream_exhausted = false;
+                        continue;
+                    }
+                    return Ok(rendered);
+                }
+                Some(f) => f,
+            };
+            if self.skip_until.is_some_and(|until| timestamp < until) {
+                let _ = tx_spare.send(frame);
+                continue;
+            }
+            self.skip_until = None;
+            let late = steps == 0 && (self.falling_behind() || self.behind_audio(timestamp));
+            let due = self.due.unwrap_or_else(Instant::now);
+            self.due = Some(due + self.config.delta_t_ms);
+            // Skip to catch up rather than play on in slow motion, as long as there's a newer
+            // frame to show instead.
+            if late && !self.queue.frames.is_empty() {
+                self.queue.dropped += 1;
+                let _ = tx_spare.send(frame);
+                continue;
+            }
+
+            let mut frame = frame;
+            let subtitle_rows = self.config.subtitle_rows();
+            if subtitle_rows > 0 {
+                crate::subtitles::push_cue(
+                    &mut frame,
+                    &self.config.subtitles,
+                    timestamp,
+                    subtitle_rows,
+                    self.config.output_columns(),
+                );
+            }
+            if self.config.hud_rows() > 0 {
+                push_status_line(
+                    &mut frame,
+                    timestamp,
+                    self.config.duration,
+                    self.config.output_columns(),
+                );
+            }
+            let render_start = Instant::now();
+            self.render_frame(&frame)?;
+            // The parser may have quit already, the buffer is just dropped then.
+            let _ = tx_spare.send(frame);
+            self.position = timestamp;
+            let latency = decoded.elapsed();
+            self.latency_total += latency;
+            if let Some(on_frame) = &mut self.on_frame {
+                on_frame(FrameStats {
+                    index: rendered,
+                    timestamp,
+                    render_time: render_start.elapsed(),
+                    latency,
+                    queue_depth: self.queue.frames.len(),
+                    dropped_so_far: self.queue.dropped,
+                });
+            }
+            rendered += 1;
+            if steps > 0 {
+                steps -= 1;
+                self.due = None;
+            }
+        }
+    }
+
+    /// Stream time between two rendered frames.
+    fn frame_interval(&self) -> Duration {
+        let fps = self.config.source_frame_rate as f64 / self.config.frame_stride as f64;
+        Duration::from_secs_f64(1.0 / fps)
+    }
+
+    fn spawn_frame_parser(
+        &self,
+        tx: mpsc::Sender<Message>,
+        spare: mpsc::Receiver<String>,
+    ) -> JoinHandle<()> {
+        let cfg = self.config.clone();
+        let decoder = Arc::clone(&self.decoder);
+        let control = Arc::clone(&self.control);
+        std::thread::spawn(move || parse_frames(&decoder, &cfg, &control, tx, spare))
+    }
+
+    fn loops_left(&self, passes: usize) -> bool {
+        self.config.loop_playback && self.config.loop_count.is_none_or(|count| passes < count)
+    }
+
+    fn render_frame(&mut self, chars: &str) -> io::Result<()> {
+        let (prefix, chars) = match self.config.redraw_mode {
+            RedrawMode::Clear => (ascii::CLEAR_SCREEN, chars),
+            RedrawMode::Home => return self.overwrite_frame(chars),
+            // A trailing newline would scroll, leaving nothing on the line to return to.
+            RedrawMode::CarriageReturn => ("\r", chars.trim_end_matches('\n')),
+            RedrawMode::Append => ("", chars),
+        };
+        self.out.write_all(prefix.as_bytes())?;
+        self.out.write_all(chars.as_bytes())?;
+        self.out.flush()
+    }
+
+    /// Draws over the previous frame, clearing what it doesn't cover.
+    fn overwrite_frame(&mut self, chars: &str) -> io::Result<()> {
+        let buf = &mut self.redraw_buf;
+        buf.clear();
+        buf.push_str(match self.screen_cleared {
+            true => ascii::CURSOR_HOME,
+            false => ascii::CLEAR_SCREEN,
+        });
+        for line in chars.split_inclusive('\n') {
+            let row = line.strip_suffix('\n');
+            buf.push_str(row.unwrap_or(line));
+            buf.push_str(ascii::CLEAR_LINE);
+            if row.is_some() {
+                buf.push('\n');
+            }
+        }
+        buf.push_str(ascii::CLEAR_BELOW);
+        self.screen_cleared = true;
+        self.out.write_all(self.redraw_buf.as_bytes())?;
+        self.out.flush()
+    }
+
+    fn reset_color(&mut self) -> io::Result<()> {
+        self.out.write_all(ascii::RESET_COLOR.as_bytes())?;
+        self.out.flush()
+    }
+
+    /// Stream position of the soundtrack, while there's one playing.
+    fn audio_position(&self) -> Option<Duration> {
+        #[cfg(feature = "audio")]
+        if let Some(audio) = &self.audio {
+            return audio.position();
+        }
+        None
+    }
+
+    fn should_skip_rendering(&self) -> bool {
+        if !self.config.realtime {
+            return false;
+        }
+        if let Some(position) = self.audio_position() {
+            // Hold the next frame until the soundtrack gets to it.
+            return self
+                .queue
+                .frames
+                .back()
+                .is_some_and(|(_, timestamp, _)| *timestamp > position);
+        }
+        self.due.is_some_and(|due| Instant::now() < due)
+    }
+
+    /// Sleeps until the next frame is due, by the clock or the soundtrack, but no longer than
+    /// `PAUSE_POLL` so commands still get through.
+    fn wait_until_due(&self) {
+        let wait = match self.audio_position() {
+            Some(position) => self
+                .queue
+                .frames
+                .back()
+                .map(|(_, timestamp, _)| timestamp.saturating_sub(position)),
+            None => self
+                .due
+                .map(|due| due.saturating_duration_since(Instant::now())),
+        };
+        std::thread::sleep(wait.unwrap_or(Duration::ZERO).min(PAUSE_POLL));
+    }
+
+    /// Whether the next frame is more than a frame late, because rendering can't keep up.
+    fn falling_behind(&self) -> bool {
+        self.config.realtime
+            && self
+                .due
+                .is_some_and(|due| Instant::now() > due + self.config.delta_t_ms)
+    }
+
+    /// Whether a frame at `timestamp` is more than a frame late for the soundtrack.
+    fn behind_audio(&self, timestamp: Duration) -> bool {
+        self.audio_position()
+            .is_some_and(|position| position > timestamp + self.config.delta_t_ms)
+    }
+}
+
+/// Appends a line with the elapsed and total time, followed by a progress bar filling the rest of
+/// `width` if there's room. Only the elapsed time is shown when the duration isn't known.
+fn push_status_line(buf: &mut String, elapsed: Duration, total: Option<Duration>, width: usize) {
+    let start = buf.len();
+    let hours = total.unwrap_or(elapsed).as_secs() >= 3600;
+    push_timecode(buf, elapsed, hours);
+    if let Some(total) = total {
+        buf.push_str(" / ");
+        push_timecode(buf, total, hours);
+        // Timecodes are ASCII, so bytes are columns.
+        let bar = width.saturating_sub(buf.len() - start + 3);
+        if bar > 0 {
+            let progress = (elapsed.as_secs_f64() / total.as_secs_f64()).clamp(0.0, 1.0);
+            let filled = (bar as f64 * progress).round() as usize;
+            buf.push_str(" [");
+            buf.extend(std::iter::repeat_n('#', filled));
+            buf.extend(std::iter::repeat_n('-', bar - filled));
+            buf.push(']');
+        }
+    }
+    buf.push('\n');
+}
+
+fn push_timecode(buf: &mut String, time: Duration, hours: bool) {
+    let secs = time.as_secs();
+    let _ = match hours {
+        true => write!(
+            buf,
+            "{}:{:02}:{:02}",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        ),
+        false => write!(buf, "{:02}:{:02}", secs / 60, secs % 60),
+    };
+}
+
+/// A decoded frame on its way to a converter, numbered in decoding order.
+struct Job {
+    seq: usize,
+    frame: video_rs::frame::RawFrame,
+    timestamp: Duration,
+    decoded: Instant,
+    epoch: usize,
+}
+
+/// What goes back in order once converted, tagged with the `Job::seq` it came from.
+enum Converted {
+    Text(String),
+    /// Luminance only, blended and rendered in order since interpolating needs the frame
+    /// before.
+    Grid(ascii::LumaGrid),
+}
+
+/// Output of the converters and the decoder, to be put back in decoding order.
+enum Output {
+    Frame {
+        seq: usize,
+        converted: Converted,
+        timestamp: Duration,
+        decoded: Instant,
+        epoch: usize,
+    },
+    /// The end of the stream or a failure, right after the frames numbered before `seq`.
+    Passed { seq: usize, message: Message },
+}
+
+impl Output {
+    fn seq(&self) -> usize {
+        match self {
+            Output::Frame { seq, .. } | Output::Passed { seq, .. } => *seq,
+        }
+    }
+}
+
+/// Threads converting frames, besides the one decoding them.
+fn converter_count() -> usize {
+    std::thread::available_parallelism()
+        .map_or(1, |cores| cores.get().saturating_sub(1))
+        .clamp(1, globals::MAX_CONVERTERS)
+}
+
+/// Decodes frames until playback is finished and hands them to a pool of converters, whose
+/// output is put back in order before it reaches the player. The decoder is only locked for
+/// one frame at a time, so the player can seek in between. Frames are converted into buffers
+/// handed back through `spare` once rendered, new ones are only allocated while the queue fills.
+fn parse_frames(
+    decoder: &Mutex<Source>,
+    cfg: &Config,
+    control: &Control,
+    tx: mpsc::Sender<Message>,
+    spare: mpsc::Receiver<String>,
+) {
+    // Bounded, so decoding can't run more than a frame per converter ahead of them.
+    let converters = converter_count();
+    let (tx_jobs, rx_jobs) = mpsc::sync_channel::<Job>(converters);
+    let (tx_out, rx_out) = mpsc::channel();
+    let rx_jobs = Arc::new(Mutex::new(rx_jobs));
+    let spare = Arc::new(Mutex::new(spare));
+    let workers: Vec<JoinHandle<()>> = (0..converters)
+        .map(|_| {
+            let (cfg, rx_jobs, spare, tx_out) = (
+                cfg.clone(),
+                Arc::clone(&rx_jobs),
+                Arc::clone(&spare),
+                tx_out.clone(),
+            );
+            std::thread::spawn(move || convert_frames(&cfg, &rx_jobs, &spare, &tx_out))
+        })
+        .collect();
+    let reorder = {
+        let (cfg, spare) = (cfg.clone(), Arc::clone(&spare));
+        std::thread::spawn(move || reorder_frames(&cfg, rx_out, &spare, &tx))
+    };
+
+    // Sending only fails once the player has stopped listening, so there's nobody left to
+    // decode for.
+    let _ = decode_frames(decoder, cfg, control, &tx_jobs, &tx_out);
+    // Closing the channels lets the converters and then the reordering run dry and quit.
+    drop((tx_jobs, tx_out));
+    for worker in workers.into_iter().chain([reorder]) {
+        if let Err(panic) = worker.join() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+/// The decoding half of `parse_frames`, numbering every frame and end of stream it passes on.
+/// Fails once nobody is taking them anymore.
+fn decode_frames(
+    decoder: &Mutex<Source>,
+    cfg: &Config,
+    control: &Control,
+    jobs: &mpsc::SyncSender<Job>,
+    out: &mpsc::Sender<Output>,
+) -> Result<(), ()> {
+    let (condvar, mutex) = &**control;
+    let time_base = decoder.lock().unwrap().time_base();
+    let mut seq = 0;
+    // Counts decoded frames for `--max-fps` from every seek on, so each pass skips the same ones.
+    let (mut index, mut index_epoch) = (0, mutex.lock().unwrap().epoch);
+    loop {
+        let (frame, epoch) = {
+            let mut decoder = decoder.lock().unwrap();
+            (
+                decoder.decode_raw(cfg.keyframes_only),
+                mutex.lock().unwrap().epoch,
+            )
+        };
+        let frame = match frame {
+            Err(video_rs::Error::ReadExhausted) => {
+                let message = Message::Exhausted(epoch);
+                out.send(Output::Passed { seq, message }).map_err(drop)?;
+                seq += 1;
+                // Nothing left to decode, unless the player seeks back.
+                let mut state = mutex.lock().unwrap();
+                while state.epoch == epoch && !state.finished {
+                    state = condvar.wait(state).unwrap();
+                }
+                if state.finished {
+                    return Ok(());
+                }
+                continue;
+            }
+            Ok(v) => v,
+            Err(e) => {
+                let message = Message::Failed(e);
+                return out.send(Output::Passed { seq, message }).map_err(drop);
+            }
+        };
+        if epoch != index_epoch {
+            (index, index_epoch) = (0, epoch);
+        }
+        let skipped = index % cfg.frame_stride != 0;
+        index += 1;
+        if skipped {
+            continue; // over --max-fps
+        }
+        let timestamp = video_rs::Time::new(frame.timestamp(), time_base).as_secs_f64();
+        let timestamp = Duration::try_from_secs_f64(timestamp).unwrap_or_default();
+        let decoded = Instant::now();
+
+        let mut state = mutex.lock().unwrap();
+        while state.signal == ControlSignal::Stop && !state.finished {
+            state = condvar.wait(state).unwrap();
+        }
+        if state.finished {
+            return Ok(());
+        }
+        drop(state);
+        let job = Job {
+            seq,
+            frame,
+            timestamp,
+            decoded,
+            epoch,
+        };
+        jobs.send(job).map_err(drop)?;
+        seq += 1;
+    }
+}
+
+/// A converter of the pool, taking jobs until decoding is over.
+fn convert_frames(
+    cfg: &Config,
+    jobs: &Mutex<mpsc::Receiver<Job>>,
+    spare: &Mutex<mpsc::Receiver<String>>,
+    out: &mpsc::Sender<Output>,
+) {
+    loop {
+        // Held only while waiting for a job, not while converting it.
+        let job = jobs.lock().unwrap().recv();
+        let Ok(job) = job else {
+            return;
+        };
+        let converted = if cfg.interpolation_steps > 1 {
+            Converted::Grid(ascii::LumaGrid::from_frame(job.frame.data(0), cfg))
+        } else {
+            let mut text = spare_buffer(cfg, spare);
+            ascii::rgb_to_ascii_buff(job.frame.data(0), cfg, &mut text);
+            Converted::Text(text)
+        };
+        let output = Output::Frame {
+            seq: job.seq,
+            converted,
+            timestamp: job.timestamp,
+            decoded: job.decoded,
+            epoch: job.epoch,
+        };
+        if out.send(output).is_err() {
+            return;
+        }
+    }
+}
+
+/// An emptied buffer the player handed back, or a new one while none are.
+fn spare_buffer(cfg: &Config, spare: &Mutex<mpsc::Receiver<String>>) -> String {
+    let spare = spare.lock().unwrap().try_recv();
+    let mut text = spare.unwrap_or_else(|_| String::with_capacity(cfg.frame_size));
+    text.clear();
+    text
+}
+
+/// Holds back whatever a converter finished early until everything numbered before it is
+/// through, so the player gets frames in decoding order.
+fn reorder_frames(
+    cfg: &Config,
+    outputs: mpsc::Receiver<Output>,
+    spare: &Mutex<mpsc::Receiver<String>>,
+    tx: &mpsc::Sender<Message>,
+) {
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+    let mut prev_grid = None;
+    for output in outputs {
+        pending.insert(output.seq(), output);
+        while let Some(output) = pending.remove(&next) {
+            next += 1;
+            if forward(cfg, output, &mut prev_grid, spare, tx).is_err() {
+                return; // the player stopped listening
+            }
+        }
+    }
+}
+
+/// Passes `output` on to the player, blending it with the grid before first when interpolating.
+/// Grids are rendered into spare buffers, as every frame the player renders is handed back.
+fn forward(
+    cfg: &Config,
+    output: Output,
+    prev_grid: &mut Option<(usize, Duration, ascii::LumaGrid)>,
+    spare: &Mutex<mpsc::Receiver<String>>,
+    tx: &mpsc::Sender<Message>,
+) -> Result<(), mpsc::SendError<Message>> {
+    let (converted, timestamp, decoded, epoch) = match output {
+        Output::Passed { message, .. } => return tx.send(message),
+        Output::Frame {
+            converted,
+            timestamp,
+            decoded,
+            epoch,
+            ..
+        } => (converted, timestamp, decoded, epoch),
+    };
+    let frame_message = |text| Message::Frame {
+        text,
+        timestamp,
+        decoded,
+        epoch,
+    };
+    let grid = match converted {
+        Converted::Text(text) => return tx.send(frame_message(text)),
+        Converted::Grid(grid) => grid,
+    };
+    let render = |grid: &ascii::LumaGrid| {
+        let mut text = spare_buffer(cfg, spare);
+        grid.push_ascii(&cfg.ascii, &mut text);
+        text
+    };
+    // Don't blend across a seek.
+    if let Some((_, prev_timestamp, prev)) = prev_grid.as_ref().filter(|(e, ..)| *e == epoch) {
+        for step in 1..cfg.interpolation_steps {
+            let t = step as f32 / cfg.interpolation_steps as f32;
+            // Placed in between as well, for the soundtrack and status line.
+            let between = *prev_timestamp + timestamp.saturating_sub(*prev_timestamp).mul_f32(t);
+            tx.send(Message::Frame {
+                text: render(&prev.lerp(&grid, t)),
+                timestamp: between,
+                decoded,
+                epoch,
+            })?;
+        }
+    }
+    tx.send(frame_message(render(&grid)))?;
+    *prev_grid = Some((epoch, timestamp, grid));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flow_control_does_not_flap_on_steady_load() {
+        let mut flow = FlowControl::new(globals::FRAME_BACKLOG);
+        let window = flow.high_watermark - flow.low_watermark;
+        let mut depth = 0;
+        let mut last = ControlSignal::Go;
+        let mut last_change = 0;
+        let mut changes = 0;
+        // The producer outpaces the consumer two to one, the usual case for short clips.
+        for tick in 0..10_000 {
+            let signal = flow.update(depth);
+            if signal != last {
+                assert!(tick - last_change >= window / 2, "flapped at tick {}", tick);
+                last = signal;
+                last_change = tick;
+                changes += 1;
+            }
+            if signal == ControlSignal::Go {
+                depth += 2;
+            }
+            depth = depth.saturating_sub(1);
+        }
+        assert!(changes > 10);
+    }
+
+    #[test]
+    fn reorder_frames_restores_decoding_order() {
+        let cfg = Config::from_args(&[String::from("tp"), String::from("clip.mp4")]).unwrap();
+        let (tx_out, rx_out) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        let frame = |seq: usize| Output::Frame {
+            seq,
+            converted: Converted::Text(seq.to_string()),
+            timestamp: Duration::ZERO,
+            decoded: Instant::now(),
+            epoch: 0,
+        };
+        for output in [frame(2), frame(0), frame(3), frame(1)] {
+            tx_out.send(output).unwrap();
+        }
+        let message = Message::Exhausted(0);
+        tx_out.send(Output::Passed { seq: 5, message }).unwrap();
+        tx_out.send(frame(4)).unwrap();
+        drop(tx_out);
+
+        let (_, spare) = mpsc::channel();
+        reorder_frames(&cfg, rx_out, &Mutex::new(spare), &tx);
+
+        let order: Vec<String> = rx
+            .try_iter()
+            .map(|message| match message {
+                Message::Frame { text, .. } => text,
+                Message::Exhausted(_) => String::from("end"),
+                Message::Failed(e) => panic!("{}", e),
+            })
+            .collect();
+        assert_eq!(order, ["0", "1", "2", "3", "4", "end"]);
+    }
+
+    #[test]
+    fn interpolated_frames_fall_in_between_in_spare_buffers() {
+        let args = ["tp", "clip.mp4", "--interpolate", "60"].map(String::from);
+        let mut cfg = Config::from_args(&args).unwrap();
+        cfg.configure_for(4, 4, 30.0);
+        assert_eq!(cfg.interpolation_steps, 2);
+        let (tx_spare, rx_spare) = mpsc::channel();
+        for _ in 0..10 {
+            tx_spare.send(String::with_capacity(64)).unwrap();
+        }
+        let (tx_out, rx_out) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        for seq in 0..4 {
+            let grid = ascii::LumaGrid {
+                cols: 2,
+                cells: vec![seq as f32 * 50.0; 4],
+            };
+            tx_out
+                .send(Output::Frame {
+                    seq,
+                    converted: Converted::Grid(grid),
+                    timestamp: Duration::from_millis(seq as u64 * 40),
+                    decoded: Instant::now(),
+                    epoch: 0,
+                })
+                .unwrap();
+        }
+        drop(tx_out);
+
+        let spare = Mutex::new(rx_spare);
+        reorder_frames(&cfg, rx_out, &spare, &tx);
+
+        // The first frame and two for each after it, every one in a buffer handed back, so
+        // what the player returns never piles up.
+        let timestamps: Vec<u128> = rx
+            .try_iter()
+            .map(|message| match message {
+                Message::Frame { timestamp, .. } => timestamp.as_millis(),
+                _ => panic!("only frames were sent"),
+            })
+            .collect();
+        assert_eq!(timestamps, [0, 20, 40, 60, 80, 100, 120]);
+        assert_eq!(spare.lock().unwrap().try_iter().count(), 3);
+    }
+
+    #[test]
+    fn missing_sample_points_at_the_variable() {
+        let sample = PlayerError::NotFound(globals::get_sample_mp4()).to_string();
+        assert!(sample.ends_with(&format!("point {} at one.", globals::SAMPLE_ENV)));
+        let clip = PlayerError::NotFound(String::from("clip.mp4")).to_string();
+        assert_eq!(clip, "Cannot find clip.mp4.");
+    }
+
+    #[test]
+    fn flow_control_holds_between_watermarks() {
+        let mut flow = FlowControl::new(100);
+        assert_eq!(flow.update(89), ControlSignal::Go);
+        assert_eq!(flow.update(90), ControlSignal::Stop);
+        assert_eq!(flow.update(70), ControlSignal::Stop);
+        assert_eq!(flow.update(51), ControlSignal::Stop);
+        assert_eq!(flow.update(50), ControlSignal::Go);
+        assert_eq!(flow.update(70), ControlSignal::Go);
+    }
+
+    #[test]
+    fn flow_control_scales_with_small_queues() {
+        let mut flow = FlowControl::new(20);
+        assert_eq!((flow.low_watermark, flow.high_watermark), (10, 18));
+        assert_eq!(flow.update(18), ControlSignal::Stop);
+        assert_eq!(flow.update(10), ControlSignal::Go);
+
+        let flow = FlowControl::new(3);
+        assert_eq!((flow.low_watermark, flow.high_watermark), (1, 2));
+    }
+
+    #[test]
+    fn frame_queue_drops_oldest_over_memory_cap() {
+        const MB: usize = 1024 * 1024;
+        let mut queue = FrameQueue::new(16, Some(4 * MB));
+        for n in 0..10u8 {
+            queue.push(
+                String::from(n as char).repeat(MB),
+                Duration::ZERO,
+                Instant::now(),
+            );
+        }
+        assert_eq!(queue.frames.len(), 4);
+        assert_eq!(queue.bytes, 4 * MB);
+        assert_eq!(queue.dropped, 6);
+        assert!(queue.pop().unwrap().0.starts_with('\u{6}'));
+    }
+
+    #[test]
+    fn status_line_fills_the_bar_by_progress() {
+        let mut line = String::new();
+        let total = Some(Duration::from_secs(100));
+        push_status_line(&mut line, Duration::from_secs(25), total, 27);
+        assert_eq!(line, "00:25 / 01:40 [###--------]\n");
+
+        line.clear();
+        push_status_line(&mut line, Duration::ZERO, total, 10);
+        assert_eq!(line, "00:00 / 01:40\n");
+    }
+
+    #[test]
+    fn status_line_without_duration_shows_elapsed_time() {
+        let mut line = String::new();
+        push_status_line(&mut line, Duration::from_secs(3725), None, 80);
+        assert_eq!(line, "1:02:05\n");
+
+        line.clear();
+        let total = Some(Duration::from_secs(4000));
+        push_status_line(&mut line, Duration::from_secs(65), total, 0);
+        assert_eq!(line, "0:01:05 / 1:06:40\n");
+    }
+
+    #[test]
+    fn frame_queue_fill_counts_memory() {
+        let mut queue = FrameQueue::new(16, Some(1000));
+        queue.push("x".repeat(500), Duration::ZERO, Instant::now());
+        assert_eq!(queue.fill(100), 50);
+        assert_eq!(FrameQueue::new(16, None).fill(100), 0);
+    }
+}
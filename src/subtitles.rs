@@ -0,0 +1,185 @@
+//! SubRip (`.srt`) subtitles, shown under the video while their cue is active.
+
+use std::time::Duration;
+
+/// One subtitle, shown from `start` until `end`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cue {
+    pub start: Duration,
+    pub end: Duration,
+    /// Text with formatting tags stripped, one entry per line.
+    pub lines: Vec<String>,
+}
+
+/// Parses the cues of a SubRip file, ordered by start time. Blocks that don't parse, or that end
+/// before they start, are skipped rather than failing the whole file.
+pub fn parse_srt(srt: &str) -> Vec<Cue> {
+    let srt = srt.trim_start_matches('\u{FEFF}').replace("\r\n", "\n");
+    let mut cues: Vec<Cue> = srt
+        .split("\n\n")
+        .filter_map(|block| {
+            let mut lines = block.lines().skip_while(|line| line.trim().is_empty());
+            let mut timing = lines.next()?;
+            // The counter is optional in practice.
+            if !timing.contains("-->") {
+                timing = lines.next()?;
+            }
+            let (start, end) = timing.split_once("-->")?;
+            let (start, end) = (parse_timestamp(start)?, parse_timestamp(end)?);
+            let lines: Vec<String> = lines.map(strip_tags).collect();
+            (start < end && !lines.is_empty()).then_some(Cue { start, end, lines })
+        })
+        .collect();
+    cues.sort_by_key(|cue| cue.start);
+    cues
+}
+
+/// `HH:MM:SS,mmm`, with a period accepted in place of the comma. Fractions of other lengths are
+/// read as decimals, past milliseconds cut off. Anything after the time, like position
+/// coordinates, is ignored.
+fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+    let timestamp = timestamp.split_whitespace().next()?;
+    let (clock, fraction) = timestamp.split_once([',', '.'])?;
+    let mut fields = clock.split(':').map(|field| field.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (fields.next()??, fields.next()??, fields.next()??);
+    if fields.next().is_some() || minutes >= 60 || seconds >= 60 {
+        return None;
+    }
+    if fraction.is_empty() || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let digits = &fraction[..fraction.len().min(3)];
+    let millis = digits.parse::<u64>().ok()? * 10u64.pow(3 - digits.len() as u32);
+    Some(Duration::from_millis(
+        ((hours * 60 + minutes) * 60 + seconds) * 1000 + millis,
+    ))
+}
+
+/// Drops `<i>`-style formatting tags, terminals can't show them.
+fn strip_tags(line: &str) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut in_tag = false;
+    for c in line.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Most lines any cue takes, the rows kept free under the video.
+pub fn max_lines(cues: &[Cue]) -> usize {
+    cues.iter().map(|cue| cue.lines.len()).max().unwrap_or(0)
+}
+
+/// The cue showing at `time`. Of overlapping cues the one that started last wins.
+pub fn active(cues: &[Cue], time: Duration) -> Option<&Cue> {
+    let started = cues.partition_point(|cue| cue.start <= time);
+    cues[..started].iter().rev().find(|cue| cue.end > time)
+}
+
+/// Appends `rows` lines, the active cue's text centered in `width` columns and blank lines
+/// making up the rest, so the image doesn't jump when cues come and go.
+pub fn push_cue(buf: &mut String, cues: &[Cue], time: Duration, rows: usize, width: usize) {
+    let lines = active(cues, time).map_or(&[][..], |cue| &cue.lines[..]);
+    for row in 0..rows {
+        if let Some(line) = lines.get(row) {
+            let len = line.chars().count();
+            buf.extend(std::iter::repeat_n(' ', width.saturating_sub(len) / 2));
+            match line.char_indices().nth(width) {
+                Some((cut, _)) if width > 0 => buf.push_str(&line[..cut]),
+                _ => buf.push_str(line),
+            }
+        }
+        buf.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRT: &str = "\u{FEFF}1\r\n\
+        00:00:01,000 --> 00:00:02,500\r\n\
+        Hello\r\n\
+        \r\n\
+        2\r\n\
+        00:00:02,000 --> 00:00:04,000 X1:0 X2:10\r\n\
+        <i>Two</i> lines\r\n\
+        of text\r\n\
+        \r\n\
+        3\r\n\
+        00:00:09,000 --> 00:00:08,000\r\n\
+        Ends before it starts\r\n\
+        \r\n\
+        garbage\r\n\
+        \r\n\
+        01:00:00.250 --> 01:00:01.000\n\
+        No counter, periods\n";
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    #[test]
+    fn parse_srt_reads_multi_line_cues_and_skips_broken_ones() {
+        let cues = parse_srt(SRT);
+        assert_eq!(cues.len(), 3);
+        assert_eq!(cues[0].start, secs(1.0));
+        assert_eq!(cues[0].end, secs(2.5));
+        assert_eq!(cues[0].lines, ["Hello"]);
+        assert_eq!(cues[1].lines, ["Two lines", "of text"]);
+        assert_eq!(cues[2].start, secs(3600.25));
+        assert_eq!(max_lines(&cues), 2);
+    }
+
+    #[test]
+    fn parse_timestamp_rejects_out_of_range_fields() {
+        assert_eq!(parse_timestamp("00:01:02,003"), Some(secs(62.003)));
+        assert_eq!(parse_timestamp("00:61:00,000"), None);
+        assert_eq!(parse_timestamp("00:00,000"), None);
+        assert_eq!(parse_timestamp("1:2:3"), None);
+    }
+
+    #[test]
+    fn parse_timestamp_scales_fractions_by_their_digits() {
+        assert_eq!(
+            parse_timestamp("00:00:01,5"),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            parse_timestamp("00:00:01.12345"),
+            Some(Duration::from_millis(1123))
+        );
+        assert_eq!(parse_timestamp("00:00:01,"), None);
+        assert_eq!(parse_timestamp("00:00:01,+5"), None);
+    }
+
+    #[test]
+    fn active_prefers_the_latest_overlapping_cue() {
+        let cues = parse_srt(SRT);
+        assert_eq!(active(&cues, secs(0.5)), None);
+        assert_eq!(active(&cues, secs(1.5)).unwrap().lines, ["Hello"]);
+        assert_eq!(active(&cues, secs(2.2)).unwrap().lines[0], "Two lines");
+        assert_eq!(active(&cues, secs(4.0)), None);
+    }
+
+    #[test]
+    fn push_cue_centers_and_pads_to_the_reserved_rows() {
+        let cues = parse_srt(SRT);
+        let mut buf = String::new();
+        push_cue(&mut buf, &cues, secs(1.0), 2, 11);
+        assert_eq!(buf, "   Hello\n\n");
+
+        buf.clear();
+        push_cue(&mut buf, &cues, secs(3.0), 2, 4);
+        assert_eq!(buf, "Two \nof t\n");
+
+        buf.clear();
+        push_cue(&mut buf, &cues, secs(5.0), 2, 11);
+        assert_eq!(buf, "\n\n");
+    }
+}
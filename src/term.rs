@@ -0,0 +1,300 @@
+/// Columns and rows of the terminal stdout is attached to, `None` if it isn't one.
+#[cfg(unix)]
+pub fn size() -> Option<(usize, usize)> {
+    // SAFETY: winsize is plain integers, all zeroes is a valid value.
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // SAFETY: TIOCGWINSZ only writes a winsize through the pointer, which is valid for that.
+    let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    (ok && size.ws_col > 0 && size.ws_row > 0)
+        .then_some((size.ws_col as usize, size.ws_row as usize))
+}
+
+#[cfg(not(unix))]
+pub fn size() -> Option<(usize, usize)> {
+    None
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use guard::TerminalGuard;
+#[cfg(unix)]
+pub use keyboard::Keyboard;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod guard {
+    use std::io::{IsTerminal, Write};
+    #[cfg(unix)]
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    };
+
+    use crate::ascii;
+
+    /// Input settings from before playback, for the interrupt handler to put back.
+    #[cfg(unix)]
+    static SAVED_TERMIOS: OnceLock<libc::termios> = OnceLock::new();
+    #[cfg(unix)]
+    static TTY_OUT: AtomicBool = AtomicBool::new(false);
+    #[cfg(unix)]
+    static ESCAPES: AtomicBool = AtomicBool::new(false);
+    /// Written by the interrupt handler, which may have cut a line short.
+    #[cfg(unix)]
+    const INTERRUPTED: &str = "\x1B[0m\x1B[?25h\n";
+
+    /// Hides the cursor for playback and puts the terminal back once dropped, whether playback
+    /// ended, failed or panicked: colors reset, cursor shown, and on a clean line. A Ctrl-C that
+    /// arrives as a signal does the same, and restores the input settings, before exiting.
+    /// With `--ascii-only` only the clean line is seen to, without a single escape.
+    pub struct TerminalGuard {
+        /// Escape codes would only garble stdout when it isn't a terminal.
+        tty_out: bool,
+        /// Cursor and colors are set by escapes, unless the output has to stay plain ASCII.
+        escapes: bool,
+        /// The last frame doesn't end with a newline.
+        single_line: bool,
+        #[cfg(unix)]
+        previous_handler: libc::sighandler_t,
+    }
+
+    impl TerminalGuard {
+        pub fn new(single_line: bool, ascii_only: bool) -> TerminalGuard {
+            let tty_out = std::io::stdout().is_terminal();
+            let escapes = tty_out && !ascii_only;
+            if escapes {
+                let mut stdout = std::io::stdout();
+                let _ = stdout.write_all(ascii::HIDE_CURSOR.as_bytes());
+                let _ = stdout.flush();
+            }
+            TerminalGuard {
+                tty_out,
+                escapes,
+                single_line,
+                #[cfg(unix)]
+                previous_handler: handle_interrupt(tty_out, escapes),
+            }
+        }
+    }
+
+    impl Drop for TerminalGuard {
+        fn drop(&mut self) {
+            // SAFETY: puts back the handler that was installed before this guard.
+            #[cfg(unix)]
+            unsafe {
+                libc::signal(libc::SIGINT, self.previous_handler);
+            }
+            if !self.tty_out {
+                return;
+            }
+            let mut stdout = std::io::stdout();
+            if self.escapes {
+                let _ = stdout.write_all(ascii::RESET_COLOR.as_bytes());
+                let _ = stdout.write_all(ascii::SHOW_CURSOR.as_bytes());
+            }
+            if self.single_line {
+                let _ = stdout.write_all(b"\n");
+            }
+            let _ = stdout.flush();
+        }
+    }
+
+    /// Saves the input settings and installs `on_interrupt`, returning the handler it replaced.
+    #[cfg(unix)]
+    fn handle_interrupt(tty_out: bool, escapes: bool) -> libc::sighandler_t {
+        TTY_OUT.store(tty_out, Ordering::Relaxed);
+        ESCAPES.store(escapes, Ordering::Relaxed);
+        if let Ok(tty) = std::fs::File::open("/dev/tty") {
+            use std::os::fd::AsRawFd;
+            // SAFETY: termios is plain integers, all zeroes is a valid value.
+            let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+            // SAFETY: the fd is open for the whole call and tcgetattr only writes to `saved`.
+            if unsafe { libc::tcgetattr(tty.as_raw_fd(), &mut saved) } == 0 {
+                // Later guards find the terminal as the first one left it anyway.
+                let _ = SAVED_TERMIOS.set(saved);
+            }
+        }
+        // SAFETY: on_interrupt only makes async-signal-safe calls.
+        unsafe {
+            libc::signal(
+                libc::SIGINT,
+                on_interrupt as *const () as libc::sighandler_t,
+            )
+        }
+    }
+
+    #[cfg(unix)]
+    extern "C" fn on_interrupt(signal: libc::c_int) {
+        // SAFETY: write, open, tcsetattr, close, signal and raise are all async-signal-safe, and
+        // the statics are only read.
+        unsafe {
+            if TTY_OUT.load(Ordering::Relaxed) {
+                // Just the newline for plain ASCII, it's all that comes after the escapes.
+                let interrupted = match ESCAPES.load(Ordering::Relaxed) {
+                    true => INTERRUPTED,
+                    false => &INTERRUPTED[INTERRUPTED.len() - 1..],
+                };
+                libc::write(
+                    libc::STDOUT_FILENO,
+                    interrupted.as_ptr().cast(),
+                    interrupted.len(),
+                );
+            }
+            if let Some(saved) = SAVED_TERMIOS.get() {
+                let fd = libc::open(c"/dev/tty".as_ptr(), libc::O_RDWR);
+                if fd >= 0 {
+                    libc::tcsetattr(fd, libc::TCSANOW, saved);
+                    libc::close(fd);
+                }
+            }
+            // Die of the signal as if it had never been caught, so the shell sees a Ctrl-C.
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+}
+
+#[cfg(unix)]
+mod keyboard {
+    use std::{
+        fs::File,
+        io::{self, Read},
+        os::fd::AsRawFd,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc, Arc,
+        },
+        thread::JoinHandle,
+    };
+
+    use crate::player::Command;
+
+    /// How long the listener waits for a key before checking whether it should stop.
+    const POLL_MS: i32 = 100;
+    /// Ctrl-C, which arrives as a plain byte with signals turned off.
+    const INTERRUPT: u8 = 0x03;
+    const RIGHT_ARROW: &[u8] = b"\x1B[C";
+    const LEFT_ARROW: &[u8] = b"\x1B[D";
+    /// How far the arrow keys seek.
+    const SEEK_STEP_MS: i64 = 10_000;
+
+    /// Reads keypresses from the controlling terminal, turning them into `Command`s. The terminal
+    /// is switched to unbuffered, silent input until this is dropped.
+    pub struct Keyboard {
+        tty: File,
+        saved: libc::termios,
+        stop: Arc<AtomicBool>,
+        listener: Option<JoinHandle<()>>,
+    }
+
+    impl Keyboard {
+        /// Starts listening, `None` when there's no terminal to listen to.
+        pub fn listen() -> Option<(Keyboard, mpsc::Receiver<Command>)> {
+            let tty = File::open("/dev/tty").ok()?;
+            let fd = tty.as_raw_fd();
+            // SAFETY: termios is plain integers, all zeroes is a valid value.
+            let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+            // SAFETY: fd is open for the whole call and tcgetattr only writes to `saved`.
+            if unsafe { libc::tcgetattr(fd, &mut saved) } != 0 {
+                return None;
+            }
+            let mut raw = saved;
+            // Keys arrive one by one without echo, Ctrl-C included so quitting goes through
+            // the same path and the terminal gets restored.
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            // SAFETY: as above, tcsetattr only reads `raw`.
+            if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+                return None;
+            }
+
+            let mut keyboard = Keyboard {
+                tty,
+                saved,
+                stop: Arc::new(AtomicBool::new(false)),
+                listener: None,
+            };
+            let (tx, rx) = mpsc::channel();
+            let tty = keyboard.tty.try_clone().ok()?;
+            let stop = Arc::clone(&keyboard.stop);
+            keyboard.listener = Some(std::thread::spawn(move || listen(tty, &stop, &tx)));
+            Some((keyboard, rx))
+        }
+    }
+
+    impl Drop for Keyboard {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(listener) = self.listener.take() {
+                let _ = listener.join();
+            }
+            // SAFETY: the fd is still open, `saved` came from tcgetattr on it.
+            unsafe { libc::tcsetattr(self.tty.as_raw_fd(), libc::TCSANOW, &self.saved) };
+        }
+    }
+
+    fn listen(mut tty: File, stop: &AtomicBool, tx: &mpsc::Sender<Command>) {
+        let mut keys = [0u8; 16];
+        while !stop.load(Ordering::Relaxed) {
+            let mut poll = libc::pollfd {
+                fd: tty.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: a single valid pollfd is passed.
+            let ready = unsafe { libc::poll(&mut poll, 1, POLL_MS) };
+            if ready < 0 && io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                return;
+            }
+            if ready <= 0 {
+                continue;
+            }
+            let read = match tty.read(&mut keys) {
+                Ok(0) | Err(_) => return,
+                Ok(read) => read,
+            };
+            for command in parse_keys(&keys[..read]) {
+                if tx.send(command).is_err() {
+                    return; // playback is over
+                }
+            }
+        }
+    }
+
+    /// Commands for the keys in one read, unbound keys are ignored.
+    pub(crate) fn parse_keys(mut keys: &[u8]) -> Vec<Command> {
+        let mut commands = Vec::new();
+        while let Some(key) = keys.first() {
+            let (command, len) = match *key {
+                _ if keys.starts_with(RIGHT_ARROW) => (Some(Command::Seek(SEEK_STEP_MS)), 3),
+                _ if keys.starts_with(LEFT_ARROW) => (Some(Command::Seek(-SEEK_STEP_MS)), 3),
+                b' ' => (Some(Command::TogglePause), 1),
+                b'n' => (Some(Command::Step), 1),
+                b'p' => (Some(Command::StepBack), 1),
+                b'q' | INTERRUPT => (Some(Command::Quit), 1),
+                _ => (None, 1),
+            };
+            commands.extend(command);
+            keys = &keys[len..];
+        }
+        commands
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn parse_keys_maps_bound_keys() {
+            assert_eq!(
+                parse_keys(b" xq\x03"),
+                [Command::TogglePause, Command::Quit, Command::Quit]
+            );
+            assert_eq!(parse_keys(b"np"), [Command::Step, Command::StepBack]);
+            assert!(parse_keys(b"abc").is_empty());
+            assert_eq!(
+                parse_keys(b"\x1B[C\x1B[D\x1B[A"),
+                [Command::Seek(10_000), Command::Seek(-10_000)]
+            );
+        }
+    }
+}
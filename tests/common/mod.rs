@@ -0,0 +1,64 @@
+//! Fixtures shared by the integration tests.
+#![allow(dead_code)]
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use terminal_player::Config;
+use video_rs::{Decoder, Location};
+
+pub const CLIP_WIDTH: usize = 64;
+pub const CLIP_HEIGHT: usize = 32;
+
+/// Writes an uncompressed y4m clip to the temp dir, so no binary fixture has to be checked in.
+/// Frame `n` is a flat gray that brightens with `n`, so consecutive frames render differently.
+pub fn write_clip(name: &str, frames: usize, fps: u32) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("terminal_player_{}.y4m", name));
+    let plane = CLIP_WIDTH * CLIP_HEIGHT;
+    let mut clip = format!(
+        "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444\n",
+        CLIP_WIDTH, CLIP_HEIGHT, fps
+    )
+    .into_bytes();
+    for n in 0..frames {
+        let luma = (16 + 219 * n / (frames - 1).max(1)) as u8;
+        clip.extend_from_slice(b"FRAME\n");
+        clip.extend(vec![luma; plane]);
+        clip.extend(vec![128; plane * 2]);
+    }
+    std::fs::write(&path, clip).expect("Failed to write test clip");
+    path
+}
+
+/// Opens `path` the same way `main` does, with `flags` passed on the command line.
+pub fn open(path: &Path, flags: &[&str]) -> (Decoder, Config) {
+    video_rs::init().unwrap();
+    let mut args = vec![String::from("terminal_player"), path.display().to_string()];
+    args.extend(flags.iter().map(|f| f.to_string()));
+    let mut cfg = Config::from_args(&args).unwrap();
+    let decoder = Decoder::new(Location::File(path.to_path_buf())).unwrap();
+    cfg.add_decoder_info(&decoder);
+    (decoder, cfg)
+}
+
+/// In-memory output that stays readable after the player took ownership of a clone.
+#[derive(Clone, Default)]
+pub struct Sink(Arc<Mutex<Vec<u8>>>);
+
+impl Sink {
+    pub fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,467 @@
+mod common;
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::Duration;
+
+use terminal_player::ascii::{CLEAR_BELOW, CLEAR_LINE, CLEAR_SCREEN, CURSOR_HOME, RESET_COLOR};
+use terminal_player::{Command, Player, PlayerError};
+
+#[test]
+fn plays_clip_to_exhaustion() {
+    let path = common::write_clip("plays_clip_to_exhaustion", 5, 25);
+    let (decoder, cfg) = common::open(&path, &["-w", "16", "--redraw-mode", "clear", "--no-hud"]);
+    let sink = common::Sink::default();
+
+    let summary = terminal_player::run_to_writer(decoder, cfg, sink.clone()).unwrap();
+
+    assert_eq!(summary.frames_rendered, 5);
+    assert_eq!(summary.frames_dropped, 0);
+    let output = sink.contents();
+    let frames: Vec<&str> = output.split(CLEAR_SCREEN).skip(1).collect();
+    assert_eq!(frames.len(), 5);
+    for frame in frames {
+        assert!(frame.lines().count() > 0);
+        assert!(frame.lines().all(|line| line.chars().count() == 16));
+    }
+}
+
+#[test]
+fn home_redraw_clears_once_and_overwrites() {
+    let path = common::write_clip("home_redraw_clears_once_and_overwrites", 4, 25);
+    let (decoder, cfg) = common::open(&path, &["-w", "16"]);
+    let sink = common::Sink::default();
+
+    terminal_player::run_to_writer(decoder, cfg, sink.clone()).unwrap();
+
+    let output = sink.contents();
+    assert!(output.starts_with(CLEAR_SCREEN));
+    assert_eq!(output.matches(CLEAR_SCREEN).count(), 1);
+    assert_eq!(output.matches(CURSOR_HOME).count(), 3);
+    assert_eq!(output.matches(CLEAR_BELOW).count(), 4);
+    let frame = output[CLEAR_SCREEN.len()..]
+        .split(CLEAR_BELOW)
+        .next()
+        .unwrap();
+    assert!(frame.lines().all(|line| line.ends_with(CLEAR_LINE)));
+}
+
+#[test]
+fn status_line_follows_each_frame() {
+    let path = common::write_clip("status_line_follows_each_frame", 5, 25);
+    let (decoder, cfg) = common::open(&path, &["-w", "32", "--redraw-mode", "clear"]);
+    let sink = common::Sink::default();
+
+    terminal_player::run_to_writer(decoder, cfg, sink.clone()).unwrap();
+
+    let output = sink.contents();
+    let status: Vec<&str> = output
+        .split(CLEAR_SCREEN)
+        .skip(1)
+        .map(|frame| frame.lines().last().unwrap())
+        .collect();
+    assert_eq!(status.len(), 5);
+    for line in &status {
+        assert!(line.starts_with("00:00 / 00:00 ["), "{:?}", line);
+        assert_eq!(line.len(), 32);
+    }
+    // 0.16s into the 0.2s clip.
+    assert_eq!(status[4].matches('#').count(), 13);
+}
+
+#[test]
+fn subtitles_show_under_the_frame_while_active() {
+    let path = common::write_clip("subtitles_show_under_the_frame_while_active", 5, 25);
+    let subs = std::env::temp_dir().join("terminal_player_subtitles.srt");
+    std::fs::write(
+        &subs,
+        "1\n00:00:00,040 --> 00:00:00,120\nHi\nthere\n\n2\n00:00:00,080 --> 00:00:00,150\nBye\n",
+    )
+    .unwrap();
+    let subs = subs.display().to_string();
+    let (decoder, cfg) = common::open(
+        &path,
+        &[
+            "-w",
+            "16",
+            "--redraw-mode",
+            "clear",
+            "--no-hud",
+            "--subs",
+            &subs,
+        ],
+    );
+    let sink = common::Sink::default();
+
+    terminal_player::run_to_writer(decoder, cfg, sink.clone()).unwrap();
+
+    let output = sink.contents();
+    let cues: Vec<Vec<&str>> = output
+        .split(CLEAR_SCREEN)
+        .skip(1)
+        .map(|frame| frame.lines().rev().take(2).collect())
+        .collect();
+    // Bottom line first, the second cue takes over where they overlap.
+    assert_eq!(
+        cues,
+        [
+            ["", ""],
+            ["     there", "       Hi"],
+            ["", "      Bye"],
+            ["", "      Bye"],
+            ["", ""],
+        ]
+    );
+}
+
+#[test]
+fn headless_playback_keeps_the_source_rate() {
+    let path = common::write_clip("headless_playback_keeps_the_source_rate", 25, 25);
+    let (decoder, cfg) = common::open(&path, &["-w", "16", "--no-hud"]);
+
+    let summary = terminal_player::run_to_writer(decoder, cfg, common::Sink::default()).unwrap();
+
+    assert_eq!(summary.frames_rendered, 25);
+    // 25 frames over the 0.96s from the first to the last, give or take startup.
+    assert!((22.0..=27.0).contains(&summary.fps()), "{}", summary);
+    assert!(
+        summary.average_latency < Duration::from_millis(500),
+        "{}",
+        summary
+    );
+}
+
+#[test]
+fn on_frame_runs_once_per_rendered_frame() {
+    let path = common::write_clip("on_frame_runs_once_per_rendered_frame", 6, 25);
+    let (decoder, cfg) = common::open(&path, &["-w", "16", "--redraw-mode", "clear"]);
+    let sink = common::Sink::default();
+    let stats = Rc::new(RefCell::new(Vec::new()));
+
+    let recorded = Rc::clone(&stats);
+    terminal_player::run_with_callback(decoder, cfg, sink.clone(), move |frame| {
+        recorded.borrow_mut().push(frame)
+    })
+    .unwrap();
+
+    let stats = stats.borrow();
+    assert_eq!(stats.len(), sink.contents().matches(CLEAR_SCREEN).count());
+    assert_eq!(stats.len(), 6);
+    assert!(stats.iter().enumerate().all(|(i, frame)| frame.index == i));
+    assert!(stats.iter().all(|frame| frame.dropped_so_far == 0));
+}
+
+#[test]
+fn color_output_is_reset_at_the_end() {
+    let path = common::write_clip("color_output_is_reset_at_the_end", 3, 25);
+    let (decoder, cfg) = common::open(&path, &["-w", "16", "--color", "--no-clear", "--no-hud"]);
+    let sink = common::Sink::default();
+
+    terminal_player::run_to_writer(decoder, cfg, sink.clone()).unwrap();
+
+    let output = sink.contents();
+    assert!(output.contains("\x1B[38;2;"));
+    assert!(output
+        .lines()
+        .all(|line| line.is_empty() || line.ends_with(RESET_COLOR)));
+    assert!(output.ends_with(RESET_COLOR));
+}
+
+#[test]
+fn commands_pause_and_quit_playback() {
+    let path = common::write_clip("commands_pause_and_quit_playback", 4, 25);
+    let play = |commands: &[Command]| {
+        let (decoder, cfg) = common::open(&path, &["-w", "16"]);
+        let mut player = Player::new(cfg, decoder, Box::new(common::Sink::default()));
+        let (tx, rx) = std::sync::mpsc::channel();
+        commands
+            .iter()
+            .for_each(|command| tx.send(*command).unwrap());
+        player.set_commands(rx);
+        player.play().unwrap().frames_rendered
+    };
+
+    assert_eq!(play(&[Command::Quit]), 0);
+    assert_eq!(play(&[Command::TogglePause, Command::Quit]), 0);
+    assert_eq!(play(&[Command::TogglePause, Command::TogglePause]), 4);
+}
+
+#[test]
+fn seek_skips_to_the_target() {
+    let path = common::write_clip("seek_skips_to_the_target", 50, 25);
+    let (decoder, cfg) = common::open(&path, &["-w", "16"]);
+    let mut player = Player::new(cfg, decoder, Box::new(common::Sink::default()));
+    let timestamps = Rc::new(RefCell::new(Vec::new()));
+    let recorded = Rc::clone(&timestamps);
+    player.set_on_frame(move |frame| recorded.borrow_mut().push(frame.timestamp));
+
+    player.seek(Duration::from_secs(1)).unwrap();
+    let summary = player.play().unwrap();
+
+    assert_eq!(summary.frames_rendered, 25);
+    assert_eq!(timestamps.borrow()[0], Duration::from_secs(1));
+}
+
+#[test]
+fn stdin_refuses_seeks_and_ignores_seek_keys() {
+    let path = common::write_clip("stdin_refuses_seeks_and_ignores_seek_keys", 10, 25);
+    let (decoder, mut cfg) = common::open(&path, &["-w", "16"]);
+    // Decoded from the file all the same, only the name tells it's a pipe.
+    cfg.file_name = String::from(terminal_player::STDIN);
+    let mut player = Player::new(cfg, decoder, Box::new(common::Sink::default()));
+    assert!(matches!(
+        player.seek(Duration::from_secs(1)),
+        Err(PlayerError::Unseekable)
+    ));
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    tx.send(Command::Seek(200)).unwrap();
+    player.set_commands(rx);
+    assert_eq!(player.play().unwrap().frames_rendered, 10);
+}
+
+#[test]
+fn seek_command_drops_frames_from_before() {
+    let path = common::write_clip("seek_command_drops_frames_from_before", 300, 100);
+    let (decoder, cfg) = common::open(&path, &["-w", "16"]);
+    let mut player = Player::new(cfg, decoder, Box::new(common::Sink::default()));
+    let timestamps = Rc::new(RefCell::new(Vec::new()));
+    let recorded = Rc::clone(&timestamps);
+    player.set_on_frame(move |frame| recorded.borrow_mut().push(frame.timestamp));
+    let (tx, rx) = std::sync::mpsc::channel();
+    tx.send(Command::Seek(2_000)).unwrap();
+    player.set_commands(rx);
+
+    player.play().unwrap();
+
+    let timestamps = timestamps.borrow();
+    assert_eq!(timestamps.len(), 100);
+    assert!(timestamps
+        .iter()
+        .all(|timestamp| *timestamp >= Duration::from_secs(2)));
+}
+
+#[test]
+fn step_mode_shows_one_frame_per_step() {
+    let path = common::write_clip("step_mode_shows_one_frame_per_step", 10, 25);
+    let (decoder, cfg) = common::open(&path, &["-w", "16", "--step"]);
+    let mut player = Player::new(cfg, decoder, Box::new(common::Sink::default()));
+    let (tx, rx) = std::sync::mpsc::channel();
+    tx.send(Command::Step).unwrap();
+    player.set_commands(rx);
+    let timestamps = Rc::new(RefCell::new(Vec::new()));
+    let recorded = Rc::clone(&timestamps);
+    // Each frame asks for the next move, once it's out.
+    player.set_on_frame(move |frame| {
+        recorded.borrow_mut().push(frame.timestamp.as_millis());
+        let next = [
+            Command::Step,
+            Command::Step,
+            Command::StepBack,
+            Command::Quit,
+        ];
+        tx.send(next[frame.index]).unwrap();
+    });
+
+    let summary = player.play().unwrap();
+
+    assert_eq!(summary.frames_rendered, 4);
+    assert_eq!(*timestamps.borrow(), [0, 40, 80, 40]);
+}
+
+#[test]
+fn loop_replays_the_whole_clip() {
+    let path = common::write_clip("loop_replays_the_whole_clip", 3, 25);
+    let (decoder, cfg) = common::open(&path, &["-w", "16", "--loop", "2"]);
+    let timestamps = Rc::new(RefCell::new(Vec::new()));
+
+    let recorded = Rc::clone(&timestamps);
+    terminal_player::run_with_callback(decoder, cfg, common::Sink::default(), move |frame| {
+        recorded.borrow_mut().push(frame.timestamp.as_millis())
+    })
+    .unwrap();
+
+    assert_eq!(*timestamps.borrow(), [0, 40, 80, 0, 40, 80]);
+}
+
+#[test]
+fn loop_skips_the_same_frames_every_pass() {
+    let path = common::write_clip("loop_skips_the_same_frames_every_pass", 4, 25);
+    let flags = ["-w", "16", "--loop", "2", "--max-fps", "12.5"];
+    let (decoder, cfg) = common::open(&path, &flags);
+    let timestamps = Rc::new(RefCell::new(Vec::new()));
+
+    let recorded = Rc::clone(&timestamps);
+    terminal_player::run_with_callback(decoder, cfg, common::Sink::default(), move |frame| {
+        recorded.borrow_mut().push(frame.timestamp.as_millis())
+    })
+    .unwrap();
+
+    assert_eq!(*timestamps.borrow(), [0, 80, 0, 80]);
+}
+
+#[test]
+fn still_prints_a_single_frame() {
+    let path = common::write_clip("still_prints_a_single_frame", 3, 25);
+    let (decoder, cfg) = common::open(&path, &["-w", "16"]);
+    let mut out = Vec::new();
+
+    terminal_player::print_still(decoder, &cfg, &mut out).unwrap();
+
+    let output = String::from_utf8(out).unwrap();
+    assert_eq!(output.lines().count(), cfg.output_size().1);
+    assert!(output.lines().all(|line| line.chars().count() == 16));
+    assert!(!output.contains('\x1B'));
+}
+
+#[test]
+fn still_goes_to_the_output_file() {
+    let path = common::write_clip("still_goes_to_the_output_file", 3, 25);
+    let export = std::env::temp_dir().join("terminal_player_still.txt");
+    let export_arg = export.display().to_string();
+    let (decoder, cfg) = common::open(&path, &["-w", "16", "--output", &export_arg]);
+
+    terminal_player::run_still(decoder, &cfg).unwrap();
+
+    let output = std::fs::read_to_string(&export).unwrap();
+    assert_eq!(output.lines().count(), cfg.output_size().1);
+    assert!(!output.contains('\x0C'));
+}
+
+#[test]
+fn render_frames_converts_the_first_frames_deterministically() {
+    let path = common::write_clip("render_frames_converts_the_first_frames", 10, 25);
+    let render = |flags: &[&str], count| {
+        let (decoder, cfg) = common::open(&path, flags);
+        let size = cfg.output_size();
+        (
+            terminal_player::render_frames(decoder, &cfg, count).unwrap(),
+            size,
+        )
+    };
+
+    let (frames, (cols, rows)) = render(&["-w", "16"], 4);
+    assert_eq!(frames.len(), 4);
+    for frame in &frames {
+        assert_eq!(frame.lines().count(), rows);
+        assert!(frame.lines().all(|line| line.chars().count() == cols));
+    }
+    // The clip brightens frame by frame.
+    assert!(frames.windows(2).all(|pair| pair[0] != pair[1]));
+    assert_eq!(render(&["-w", "16"], 4).0, frames);
+
+    let (all, _) = render(&["-w", "16"], 50);
+    assert_eq!(all.len(), 10);
+    // Every other frame to stay under the rate.
+    let (halved, _) = render(&["-w", "16", "--max-fps", "12.5"], 3);
+    assert_eq!(halved, [&all[0][..], &all[2], &all[4]]);
+}
+
+#[test]
+fn output_file_gets_every_frame_between_form_feeds() {
+    let path = common::write_clip("output_file_gets_every_frame_between_form_feeds", 30, 25);
+    let export = std::env::temp_dir().join("terminal_player_export.txt");
+    let export_arg = export.display().to_string();
+    let (decoder, cfg) = common::open(&path, &["-w", "16", "--color", "--output", &export_arg]);
+    let rows = cfg.output_size().1;
+
+    let summary = terminal_player::run(decoder, cfg).unwrap();
+
+    assert_eq!(summary.frames_rendered, 30);
+    assert_eq!(summary.frames_dropped, 0);
+    // Faster than real time, nothing waits for the clock.
+    assert!(summary.duration < Duration::from_millis(1000));
+    let output = std::fs::read_to_string(&export).unwrap();
+    let frames: Vec<&str> = output.split('\x0C').collect();
+    assert_eq!(frames.len(), 30);
+    assert!(frames
+        .iter()
+        .all(|frame| frame.matches('\n').count() == rows));
+    assert!(output.ends_with(RESET_COLOR));
+}
+
+#[test]
+fn output_directory_gets_a_file_per_frame() {
+    let path = common::write_clip("output_directory_gets_a_file_per_frame", 4, 25);
+    let dir = std::env::temp_dir().join("terminal_player_export_frames");
+    let _ = std::fs::remove_dir_all(&dir);
+    let dir_arg = format!("{}/", dir.display());
+    let (decoder, cfg) = common::open(&path, &["-w", "16", "--output", &dir_arg]);
+
+    terminal_player::run(decoder, cfg).unwrap();
+
+    let mut files: Vec<String> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    files.sort();
+    assert_eq!(
+        files,
+        ["000000.txt", "000001.txt", "000002.txt", "000003.txt"]
+    );
+    let first = std::fs::read_to_string(dir.join(&files[0])).unwrap();
+    assert!(first.lines().all(|line| line.chars().count() == 16));
+    assert!(!first.contains('\x1B'));
+}
+
+/// Output that takes `delay` to take each frame, like a terminal that can't keep up.
+struct Slow {
+    sink: common::Sink,
+    delay: Duration,
+}
+
+impl Write for Slow {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.sink.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        std::thread::sleep(self.delay);
+        Ok(())
+    }
+}
+
+#[test]
+fn slow_output_drops_frames_to_keep_up() {
+    let path = common::write_clip("slow_output_drops_frames_to_keep_up", 30, 25);
+    let (decoder, cfg) = common::open(&path, &["-w", "16"]);
+    let out = Slow {
+        sink: common::Sink::default(),
+        delay: Duration::from_millis(100),
+    };
+
+    let summary = terminal_player::run_to_writer(decoder, cfg, out).unwrap();
+
+    assert!(summary.frames_dropped > 0);
+    assert_eq!(summary.frames_rendered + summary.frames_dropped, 30);
+    // Real time would be 1.2s, slow motion 3s.
+    assert!(
+        summary.duration < Duration::from_millis(2000),
+        "{:?}",
+        summary
+    );
+}
+
+struct BrokenPipe;
+
+impl Write for BrokenPipe {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn write_failure_is_returned() {
+    let path = common::write_clip("write_failure_is_returned", 5, 25);
+    let (decoder, cfg) = common::open(&path, &["-w", "16"]);
+
+    match terminal_player::run_to_writer(decoder, cfg, BrokenPipe) {
+        Err(PlayerError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::BrokenPipe),
+        other => panic!("expected an IO error, got {:?}", other),
+    }
+}